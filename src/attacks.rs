@@ -0,0 +1,199 @@
+use crate::position::{BitBoard, Color, Square};
+use std::sync::OnceLock;
+
+const fn leaper_attacks(offsets: &[(i8, i8)]) -> [u64; 64] {
+    let mut result = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        let rank = (square / 8) as i8;
+        let file = (square % 8) as i8;
+        let mut i = 0;
+        while i < offsets.len() {
+            let (rank_diff, file_diff) = offsets[i];
+            let (rank, file) = (rank + rank_diff, file + file_diff);
+            if 0 <= rank && rank < 8 && 0 <= file && file < 8 {
+                result[square] |= 1 << (rank * 8 + file);
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    result
+}
+
+const KNIGHT_ATTACKS: [u64; 64] = leaper_attacks(&[
+    /*    */  (-2, -1), /*    */  (-2,  1), /*    */
+    (-1, -2), /*    */  /*    */  /*    */  (-1,  2),
+    /*    */  /*    */  /*    */  /*    */  /*    */
+    ( 1, -2), /*    */  /*    */  /*    */  ( 1,  2),
+    /*    */  ( 2, -1), /*    */  ( 2,  1), /*    */
+]);
+
+const KING_ATTACKS: [u64; 64] = leaper_attacks(&[
+    (-1, -1), (-1,  0), (-1, 1),
+    ( 0, -1), /*    */  ( 0, 1),
+    ( 1, -1), ( 1,  0), ( 1, 1),
+]);
+
+const WHITE_PAWN_ATTACKS: [u64; 64] = leaper_attacks(&[(1, -1), (1, 1)]);
+const BLACK_PAWN_ATTACKS: [u64; 64] = leaper_attacks(&[(-1, -1), (-1, 1)]);
+
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(-1, 0), (0, -1), (0, 1), (1, 0)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+
+pub fn knight_attacks(square: Square) -> BitBoard {
+    BitBoard(KNIGHT_ATTACKS[square.to_bit_position() as usize])
+}
+
+pub fn king_attacks(square: Square) -> BitBoard {
+    BitBoard(KING_ATTACKS[square.to_bit_position() as usize])
+}
+
+/// The squares a pawn of `color` captures on; pushes are not included.
+pub fn pawn_attacks(color: Color, square: Square) -> BitBoard {
+    match color {
+        Color::White => BitBoard(WHITE_PAWN_ATTACKS[square.to_bit_position() as usize]),
+        Color::Black => BitBoard(BLACK_PAWN_ATTACKS[square.to_bit_position() as usize]),
+    }
+}
+
+pub fn rook_attacks(square: Square, occupied: BitBoard) -> BitBoard {
+    let tables = sliding_tables();
+    BitBoard(tables.attacks[tables.rook[square.to_bit_position() as usize].index(occupied.0)])
+}
+
+pub fn bishop_attacks(square: Square, occupied: BitBoard) -> BitBoard {
+    let tables = sliding_tables();
+    BitBoard(tables.attacks[tables.bishop[square.to_bit_position() as usize].index(occupied.0)])
+}
+
+pub fn queen_attacks(square: Square, occupied: BitBoard) -> BitBoard {
+    BitBoard(rook_attacks(square, occupied).0 | bishop_attacks(square, occupied).0)
+}
+
+/// Walks the rays from `square`, stopping at (and including) the first occupied square.
+fn ray_attacks(square: usize, occupied: u64, directions: &[(i8, i8)]) -> u64 {
+    let mut result = 0;
+    for &(rank_diff, file_diff) in directions {
+        let (mut rank, mut file) = ((square / 8) as i8, (square % 8) as i8);
+        loop {
+            rank += rank_diff;
+            file += file_diff;
+            if !(0..8).contains(&rank) || !(0..8).contains(&file) { break }
+            let bit = 1 << (rank * 8 + file);
+            result |= bit;
+            if occupied & bit != 0 { break }
+        }
+    }
+    result
+}
+
+/// The squares whose occupancy matters for a slider on `square`:
+/// its rays, without the last square of each, since nothing lies beyond it.
+fn relevant_occupancy(square: usize, directions: &[(i8, i8)]) -> u64 {
+    let mut result = 0;
+    for &(rank_diff, file_diff) in directions {
+        let (mut rank, mut file) = ((square / 8) as i8, (square % 8) as i8);
+        loop {
+            rank += rank_diff;
+            file += file_diff;
+            let (next_rank, next_file) = (rank + rank_diff, file + file_diff);
+            if !(0..8).contains(&next_rank) || !(0..8).contains(&next_file) { break }
+            result |= 1 << (rank * 8 + file);
+        }
+    }
+    result
+}
+
+// multipliers that send every relevant occupancy of a square to a slot holding the right attacks,
+// found once by trying random sparse numbers until one maps no two occupancies
+// with different attacks to the same slot.
+const ROOK_MAGICS: [u64; 64] = [
+    0x2080004008201282, 0x8040002000401000, 0x2100200008410010, 0x0600120004084020,
+    0x0300180010030004, 0x8900440001001802, 0x1080010002000080, 0x010003000440228a,
+    0x2400800080400024, 0x0001804000802000, 0x0800801000802000, 0x8501002410000900,
+    0x0841800400080080, 0x420a001002000884, 0x6081000401000200, 0x0005001042008100,
+    0x0000288000401080, 0x4100810040002108, 0x0020808020001000, 0x0021030010002008,
+    0x0018010008050010, 0x3030080120100440, 0x0010040038104112, 0x00a0220001008044,
+    0x0200802080004000, 0x0040010100204083, 0x1180100080802000, 0x0200080080801000,
+    0x0290080080800400, 0x0212000200040810, 0x4000630400081086, 0x0210004200008104,
+    0x1080804008800020, 0x8001200041401000, 0xc000200282801008, 0x4800100009002100,
+    0x2a02041101000800, 0x0080040080800200, 0x0042020844003021, 0x0000808402002059,
+    0x0140882040008000, 0x801000200040400a, 0x0042002010820041, 0x180020400a020010,
+    0x0300080004008080, 0x3049000204010008, 0x4001024801a40010, 0x0000408400720009,
+    0x1000468001006500, 0x00008461400b0100, 0x0202004218208200, 0x0400082100100100,
+    0x0448080004008080, 0x1000800200040080, 0x0000500102880400, 0x0008410040840200,
+    0x0841001840e28202, 0x2402221081084001, 0x0047002000400811, 0x0002201000080501,
+    0x0012002110480482, 0x0016002408011022, 0x0000080082500124, 0x00240d0050802402,
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0x0004084204003200, 0x0014818604010002, 0x0008080100248000, 0x1024410120800000,
+    0x0842021004921070, 0x0411100804000022, 0x0074140404041000, 0x8400820052024040,
+    0x00e0103006928410, 0x2090020802140050, 0x00a018180101a002, 0x0805282a00222040,
+    0x0445440421010808, 0x1414028290409040, 0x0100020211608800, 0x1c00008400829000,
+    0x1121000404040841, 0x0424046001045104, 0x4202000c0c001204, 0x411c002240108000,
+    0x0604000220a00203, 0x3414408088084000, 0x0010400484501810, 0x0001004020880400,
+    0x1120a200100c0b08, 0x4004422220424400, 0x000c020014080e50, 0x0400802008020021,
+    0x0810840004802000, 0x0600410002010140, 0x3204010000415041, 0x180400d41105c210,
+    0x0804022021c00450, 0x0c20820904e00800, 0x00c0119000180040, 0x9004020080080081,
+    0x2040050100141040, 0x05060281004e0071, 0x200a808202430825, 0xc0810c0020110104,
+    0x2014425240143000, 0x0004010410100220, 0x2000101088001000, 0x0004a02128000401,
+    0x0406c00812000842, 0x4220200040400082, 0x0408980804802c62, 0x0408010106040028,
+    0x0934010410040800, 0x0502020104020a00, 0x1800260084114040, 0x0000818020880000,
+    0x1000842044242200, 0x0808c05214050010, 0x0821048400840401, 0x00100c030c023000,
+    0x0000210042202000, 0x1040150c00840408, 0x0808909100809011, 0x800028000504a801,
+    0x0060c10210202200, 0x00020460a0a20880, 0x00001020491400a8, 0x0020020202040812,
+];
+
+/// Maps the relevant occupancy of a square to its slot in the shared attack table.
+#[derive(Copy, Clone)]
+struct Magic {
+    mask: u64,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: u64) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct SlidingTables {
+    rook: [Magic; 64],
+    bishop: [Magic; 64],
+    attacks: Vec<u64>,
+}
+
+fn sliding_tables() -> &'static SlidingTables {
+    static TABLES: OnceLock<SlidingTables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut attacks = vec![];
+        let rook = std::array::from_fn(|square| fill_magic(square, ROOK_MAGICS[square], &ROOK_DIRECTIONS, &mut attacks));
+        let bishop = std::array::from_fn(|square| fill_magic(square, BISHOP_MAGICS[square], &BISHOP_DIRECTIONS, &mut attacks));
+        SlidingTables{ rook, bishop, attacks }
+    })
+}
+
+/// Appends the slots of a square to `attacks`, walking the rays once for every occupancy of the relevant squares.
+fn fill_magic(square: usize, magic: u64, directions: &[(i8, i8)], attacks: &mut Vec<u64>) -> Magic {
+    let mask = relevant_occupancy(square, directions);
+    let bits = mask.count_ones();
+    let result = Magic{ mask, magic, shift: 64 - bits, offset: attacks.len() };
+    attacks.resize(attacks.len() + (1 << bits), 0);
+
+    // enumerates every subset of the mask.
+    let mut occupied = 0u64;
+    loop {
+        let reachable = ray_attacks(square, occupied, directions);
+        let slot = &mut attacks[result.index(occupied)];
+        debug_assert!(*slot == 0 || *slot == reachable, "magic collision on square {square}");
+        *slot = reachable;
+        occupied = occupied.wrapping_sub(mask) & mask;
+        if occupied == 0 { break }
+    }
+
+    result
+}
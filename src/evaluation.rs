@@ -1,11 +1,14 @@
-use crate::position;
-use crate::moves;
+use crate::attacks;
+use crate::position::{self, Piece};
+use serde::{Deserialize, Serialize};
 
 const KNIGHT_VALUE: isize = 3;
 const BISHOP_VALUE: isize = 3;
 const ROOK_VALUE: isize = 5;
 const QUEEN_VALUE: isize = 9;
 
+/// The endgame bonus for a king that is alone on its rank, and so moves as a king,
+/// with all the squares around it free.
 const KING_MOBILITY_BONUS: isize = 50;
 
 const PAWN_MASK: u64 = u64::from_be_bytes([
@@ -74,58 +77,478 @@ const KING_MASK: u64 = u64::from_be_bytes([
     0b11111111,
 ]);
 
-const fn byte_swap(m: u64) -> u64 { u64::from_be_bytes(m.to_le_bytes()) }
+/// The score of a pawn's worth of material.
+pub const PAWN_SCORE: isize = 10000;
 
-pub fn evaluate(pos: &position::Position) -> isize {
+/// One value for each kind of piece.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PerPiece<T> {
+    pub pawn: T,
+    pub knight: T,
+    pub bishop: T,
+    pub rook: T,
+    pub queen: T,
+    pub king: T,
+}
+
+impl<T> PerPiece<T> {
+    pub fn get(&self, piece: Piece) -> &T {
+        match piece {
+            Piece::Pawn   => &self.pawn,
+            Piece::Knight => &self.knight,
+            Piece::Bishop => &self.bishop,
+            Piece::Rook   => &self.rook,
+            Piece::Queen  => &self.queen,
+            Piece::King   => &self.king,
+        }
+    }
+
+    pub fn get_mut(&mut self, piece: Piece) -> &mut T {
+        match piece {
+            Piece::Pawn   => &mut self.pawn,
+            Piece::Knight => &mut self.knight,
+            Piece::Bishop => &mut self.bishop,
+            Piece::Rook   => &mut self.rook,
+            Piece::Queen  => &mut self.queen,
+            Piece::King   => &mut self.king,
+        }
+    }
+}
+
+/// A value for every square, from white's point of view; black looks it up mirrored vertically.
+///
+/// In parameter files it is written as 8 ranks from rank 8 down to rank 1,
+/// each a string with the values for the files from A to H, separated by spaces.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SquareTable(pub [isize; 64]);
+
+impl SquareTable {
+    /// `value` on the squares of `mask`, 0 elsewhere.
+    const fn from_mask(mask: u64, value: isize) -> Self {
+        let mut result = [0; 64];
+        let mut square = 0;
+        while square < 64 {
+            if mask & 1 << square != 0 {
+                result[square] = value;
+            }
+            square += 1;
+        }
+        SquareTable(result)
+    }
+
+    pub fn get(&self, color: position::Color, square: position::Square) -> isize {
+        let index = square.to_bit_position() as usize;
+        match color {
+            position::Color::White => self.0[index],
+            position::Color::Black => self.0[index ^ 56],
+        }
+    }
+}
+
+impl Serialize for SquareTable {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ranks = self.0
+            .chunks(8)
+            .rev()
+            .map(|values| values.iter().map(|value| format!("{value:>4}")).collect::<String>())
+            .collect::<Vec<_>>();
+        ranks.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SquareTable {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let ranks = Vec::<String>::deserialize(deserializer)?;
+        if ranks.len() != 8 {
+            return Err(serde::de::Error::custom(format!("expected 8 ranks, found {}", ranks.len())));
+        }
+
+        let mut result = [0; 64];
+        for (i, line) in ranks.iter().enumerate() {
+            let rank = 7 - i;
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<isize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| serde::de::Error::custom(format!("rank {}: {err}", rank + 1)))?;
+            if values.len() != 8 {
+                return Err(serde::de::Error::custom(format!("rank {} does not have 8 squares", rank + 1)));
+            }
+            result[rank * 8..rank * 8 + 8].copy_from_slice(&values);
+        }
+        Ok(SquareTable(result))
+    }
+}
+
+/// A weight with a value at the start of the game and one for the endgame;
+/// in between, the two are blended by [`game_phase`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tapered<T> {
+    pub middlegame: T,
+    pub endgame: T,
+}
+
+impl<T: Copy> Tapered<T> {
+    /// The same value in every phase of the game.
+    const fn flat(value: T) -> Self {
+        Tapered{ middlegame: value, endgame: value }
+    }
+
+    pub fn get_mut(&mut self, endgame: bool) -> &mut T {
+        if endgame { &mut self.endgame } else { &mut self.middlegame }
+    }
+}
+
+/// Blends middlegame and endgame values for one position.
+#[derive(Copy, Clone, Debug)]
+struct Taper {
+    phase: isize,
+    full_phase: isize,
+}
+
+impl Taper {
+    fn blend(&self, middlegame: isize, endgame: isize) -> isize {
+        (middlegame * self.phase + endgame * (self.full_phase - self.phase)) / self.full_phase
+    }
+
+    fn value(&self, weight: &Tapered<isize>) -> isize {
+        self.blend(weight.middlegame, weight.endgame)
+    }
+}
+
+/// The weights used by [`evaluate`]. Missing fields in a parameter file take their default value,
+/// see [`EvalParams::from_toml`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvalParams {
+    /// the score of one unit of material.
+    pub material_scale: isize,
+    /// the score of one unit of virtual material.
+    pub virtual_material_scale: isize,
+    /// the score of one unit of the piece square and king mobility terms.
+    pub positional_scale: isize,
+    /// the material value of each piece, in pawns.
+    pub piece_values: PerPiece<Tapered<isize>>,
+    /// how much each piece on the board counts towards the middlegame, see [`game_phase`].
+    pub phase_weights: PerPiece<isize>,
+    /// how well placed each piece is, by the piece it moves like; for the king, by the physical king.
+    pub piece_squares: PerPiece<Tapered<SquareTable>>,
+    /// the bonus for the king, by the piece it moves like.
+    pub king_mobility: PerPiece<Tapered<isize>>,
+    /// the bonus for a king alone on its rank, which moves as a king,
+    /// with all 8 squares around it free; it shrinks with the squares it cannot move to.
+    pub lone_king_activity: Tapered<isize>,
+    /// the bonus for a pawn on each rank, counted from its own side, so the last entries are close to promotion.
+    pub pawn_advancement: Tapered<[isize; 8]>,
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        EvalParams{
+            material_scale: PAWN_SCORE,
+            virtual_material_scale: 1000,
+            positional_scale: 100,
+            piece_values: PerPiece{
+                pawn: Tapered::flat(1),
+                knight: Tapered::flat(KNIGHT_VALUE),
+                bishop: Tapered::flat(BISHOP_VALUE),
+                rook: Tapered::flat(ROOK_VALUE),
+                queen: Tapered::flat(QUEEN_VALUE),
+                king: Tapered::flat(0),
+            },
+            phase_weights: PerPiece{ pawn: 0, knight: 1, bishop: 1, rook: 2, queen: 4, king: 0 },
+            piece_squares: PerPiece{
+                pawn:   Tapered::flat(SquareTable::from_mask(PAWN_MASK,   1)),
+                knight: Tapered::flat(SquareTable::from_mask(KNIGHT_MASK, 2)),
+                bishop: Tapered::flat(SquareTable::from_mask(BISHOP_MASK, 2)),
+                rook:   Tapered::flat(SquareTable::from_mask(ROOK_MASK,   5)),
+                queen:  Tapered::flat(SquareTable::from_mask(QUEEN_MASK,  5)),
+                // the back ranks shelter the king until the endgame, where it should join the game.
+                king: Tapered{
+                    middlegame: SquareTable::from_mask(KING_MASK, 20),
+                    endgame:    SquareTable::from_mask(KING_MASK, 0),
+                },
+            },
+            king_mobility: PerPiece{
+                pawn: Tapered::flat(-10),
+                knight: Tapered::flat(4),
+                bishop: Tapered::flat(6),
+                rook: Tapered::flat(8),
+                queen: Tapered::flat(10),
+                king: Tapered::flat(5),
+            },
+            lone_king_activity: Tapered{ middlegame: 0, endgame: KING_MOBILITY_BONUS },
+            pawn_advancement: Tapered{
+                middlegame: [0, 0, 0, 0, 2, 5, 10, 0],
+                endgame:    [0, 0, 0, 5, 10, 20, 40, 0],
+            },
+        }
+    }
+}
+
+/// Overwrites the values in `base` with the ones in `overrides`, descending into tables.
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(value)) => merge_tables(base, value),
+            (_, value) => { base.insert(key, value); },
+        }
+    }
+}
+
+impl EvalParams {
+    /// Reads a parameter file. Every field it leaves out keeps its default value, however deeply
+    /// it is nested, so that `[piece_values.knight]` with only `middlegame` is enough to change one weight.
+    /// Phase weights must not be negative, and must not all be 0, see [`game_phase`].
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        let mut table = toml::Table::try_from(Self::default()).expect("parameters can be written as toml");
+        merge_tables(&mut table, toml::from_str(text)?);
+        let params: Self = table.try_into()?;
+
+        if let Some(piece) = PIECES.into_iter().find(|&piece| *params.phase_weights.get(piece) < 0) {
+            let piece = format!("{piece:?}").to_lowercase();
+            return Err(serde::de::Error::custom(format!("phase_weights.{piece} must not be negative")));
+        }
+        if params.full_phase() == 0 {
+            return Err(serde::de::Error::custom("phase_weights must not all be 0"));
+        }
+        Ok(params)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("parameters can be written as toml")
+    }
+
+    /// The phase of the starting position, where the middlegame tables apply in full.
+    pub fn full_phase(&self) -> isize {
+        let start = [(Piece::Pawn, 8), (Piece::Knight, 2), (Piece::Bishop, 2), (Piece::Rook, 2), (Piece::Queen, 1)];
+        start.into_iter().map(|(piece, count)| 2 * count * self.phase_weights.get(piece)).sum()
+    }
+}
+
+/// How far the position is from the endgame: the [`EvalParams::full_phase`] with all the pieces
+/// of the starting position on the board, down to 0 as they are exchanged.
+/// Promoted pieces cannot raise it above the full phase.
+pub fn game_phase(pos: &position::Position, params: &EvalParams) -> isize {
+    let phase = PIECES
+        .into_iter()
+        .map(|piece| {
+            let count = pos.white.pieces(piece).size() + pos.black.pieces(piece).size();
+            count as isize * params.phase_weights.get(piece)
+        })
+        .sum::<isize>();
+    phase.clamp(0, params.full_phase())
+}
+
+/// The score of one evaluation term for each side, each from its own point of view.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TermScore {
+    pub white: isize,
+    pub black: isize,
+}
+
+impl TermScore {
+    fn from_counts(white: isize, black: isize, scale: isize) -> Self {
+        Self{ white: white * scale, black: black * scale }
+    }
+
+    /// The score from white's point of view.
+    pub fn difference(&self) -> isize {
+        self.white - self.black
+    }
+}
+
+impl std::ops::Add for TermScore {
+    type Output = TermScore;
+    fn add(self, other: TermScore) -> Self::Output {
+        TermScore{ white: self.white + other.white, black: self.black + other.black }
+    }
+}
+
+/// The terms that make up [`evaluate`], kept apart so that they can be inspected.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EvalTrace {
+    /// the [`game_phase`] the position was evaluated in, out of `full_phase`.
+    pub phase: isize,
+    pub full_phase: isize,
+    /// the pieces on the board.
+    pub material: TermScore,
+    /// the pieces the pieces on the board move like.
+    pub virtual_material: TermScore,
+    /// how well placed each piece is for the piece it moves like.
+    pub piece_squares: TermScore,
+    /// how freely the king moves, depending on the piece to its left.
+    pub king_mobility: TermScore,
+    /// how active a king alone on its rank is.
+    pub king_activity: TermScore,
+    /// how close the pawns are to promotion.
+    pub pawn_advancement: TermScore,
+}
+
+impl EvalTrace {
+    pub fn total(&self) -> TermScore {
+        self.material + self.virtual_material + self.piece_squares + self.king_mobility
+            + self.king_activity + self.pawn_advancement
+    }
+
+    /// The evaluation from the point of view of `color`.
+    pub fn score(&self, color: position::Color) -> isize {
+        match color {
+            position::Color::White =>  self.total().difference(),
+            position::Color::Black => -self.total().difference(),
+        }
+    }
+}
+
+impl std::fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("phase {}/{}\n\n", self.phase, self.full_phase))?;
+        f.write_fmt(format_args!("{:<18}{:>10}{:>10}{:>10}\n", "term", "white", "black", "total"))?;
+        for (name, term) in [
+            ("material",         self.material),
+            ("virtual material", self.virtual_material),
+            ("piece squares",    self.piece_squares),
+            ("king mobility",    self.king_mobility),
+            ("king activity",    self.king_activity),
+            ("pawn advancement", self.pawn_advancement),
+            ("total",            self.total()),
+        ] {
+            f.write_fmt(format_args!("{:<18}{:>10}{:>10}{:>10}\n", name, term.white, term.black, term.difference()))?;
+        }
+        Ok(())
+    }
+}
+
+const PIECES: [Piece; 6] = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King];
+
+fn material(pieces: &position::PlayerPieces, params: &EvalParams, taper: Taper) -> isize {
+    PIECES
+        .into_iter()
+        .map(|piece| pieces.pieces(piece).size() as isize * taper.value(params.piece_values.get(piece)))
+        .sum()
+}
+
+/// Scores the position from the point of view of the side to move.
+pub fn evaluate(pos: &position::Position, params: &EvalParams) -> isize {
     if pos.player(pos.turn).kings.size() != 1  { return isize::MIN }
     if pos.player(!pos.turn).kings.size() != 1 { return isize::MAX }
 
-    let sign = match pos.turn { 
-        position::Color::Black => -1,
-        position::Color::White =>  1,
+    trace(pos, params).score(pos.turn)
+}
+
+/// Computes every term of [`evaluate`] separately.
+pub fn trace(pos: &position::Position, params: &EvalParams) -> EvalTrace {
+    let virt = pos.to_virtual_position();
+    let phase = game_phase(pos, params);
+    // guards against dividing by zero when every phase weight is set to 0.
+    let full_phase = params.full_phase().max(1);
+    let taper = Taper{ phase, full_phase };
+
+    let mut piece_happiness = TermScore::default();
+    for piece in PIECES {
+        // the king is scored where it stands, whatever it moves like.
+        let (white_board, black_board) = match piece {
+            Piece::King => (pos.white.kings, pos.black.kings),
+            _ => (*virt.white.pieces(piece), *virt.black.pieces(piece)),
+        };
+        let table = params.piece_squares.get(piece);
+        let blend = |color, square| taper.blend(table.middlegame.get(color, square), table.endgame.get(color, square));
+        piece_happiness.white += white_board.into_iter().map(|square| blend(position::Color::White, square)).sum::<isize>();
+        piece_happiness.black += black_board.into_iter().map(|square| blend(position::Color::Black, square)).sum::<isize>();
+    }
+
+    // the king moves like the piece to its left, which is what its virtual piece records.
+    let king_mobility = |virtual_pieces: &position::PlayerPieces, kings: position::BitBoard| {
+        kings
+            .into_iter()
+            .map(|square| taper.value(params.king_mobility.get(virtual_pieces.piece_at(square).expect("the king is on the board"))))
+            .sum::<isize>()
     };
 
-    let material_evaluation =
-        (pos.white.pawns.size()   as isize - pos.black.pawns.size()   as isize)                +
-        (pos.white.knights.size() as isize - pos.black.knights.size() as isize) * KNIGHT_VALUE +
-        (pos.white.bishops.size() as isize - pos.black.bishops.size() as isize) * BISHOP_VALUE +
-        (pos.white.rooks.size()   as isize - pos.black.rooks.size()   as isize) * ROOK_VALUE   +
-        (pos.white.queens.size()  as isize - pos.black.queens.size()  as isize) * QUEEN_VALUE;
+    // a king alone on its rank moves as a king, and is worth more the more squares it can reach.
+    let king_activity = |color: position::Color| {
+        let own = pos.player(color).all();
+        pos.player(color).kings
+            .into_iter()
+            .filter(|&square| pos.all().0 & 0xff << (square.rank() * 8) == 1 << square.to_bit_position())
+            .map(|square| {
+                let free = (attacks::king_attacks(square).0 & !own.0).count_ones() as isize;
+                taper.value(&params.lone_king_activity) * free / 8
+            })
+            .sum::<isize>()
+    };
 
-    let virt = pos.to_virtual_position();
-    let virt_evaluation =
-        (virt.white.pawns.size()   as isize - virt.black.pawns.size()   as isize)                +
-        (virt.white.knights.size() as isize - virt.black.knights.size() as isize) * KNIGHT_VALUE +
-        (virt.white.bishops.size() as isize - virt.black.bishops.size() as isize) * BISHOP_VALUE +
-        (virt.white.rooks.size()   as isize - virt.black.rooks.size()   as isize) * ROOK_VALUE   +
-        (virt.white.queens.size()  as isize - virt.black.queens.size()  as isize) * QUEEN_VALUE;
-
-    let mut piece_happiness = 0;
-    for (white_board, black_board, mask, weight) in [
-        (virt.white.pawns.0,   virt.black.pawns.0,   PAWN_MASK,   1),
-        (virt.white.knights.0, virt.black.knights.0, KNIGHT_MASK, 2),
-        (virt.white.bishops.0, virt.black.bishops.0, BISHOP_MASK, 2),
-        (virt.white.rooks.0,   virt.black.rooks.0,   ROOK_MASK,   5),
-        (virt.white.queens.0,  virt.black.queens.0,  QUEEN_MASK,  5),
-        (pos.white.kings.0,    pos.black.kings.0,    KING_MASK,   20),
-    ] {
-        piece_happiness += weight 
-            * ( (white_board & mask).count_ones() as isize - (black_board & byte_swap(mask)).count_ones() as isize);
-    }
-
-    let mobility_score = |piece|  match piece {
-        position::Piece::Queen  => 10,
-        position::Piece::Rook   => 8,
-        position::Piece::Bishop => 6,
-        position::Piece::King   => 5,
-        position::Piece::Knight => 4,
-        position::Piece::Pawn   => -10,
+    let pawn_advancement = |color: position::Color| {
+        pos.player(color).pawns
+            .into_iter()
+            .map(|square| {
+                let rank = match color {
+                    position::Color::White => square.rank(),
+                    position::Color::Black => 7 - square.rank(),
+                } as usize;
+                let advancement = &params.pawn_advancement;
+                taper.blend(advancement.middlegame[rank], advancement.endgame[rank])
+            })
+            .sum::<isize>()
     };
 
-    piece_happiness += 
-        mobility_score(pos.white.piece_at(pos.white.kings.into_iter().next().unwrap()).unwrap()) -
-        mobility_score(pos.black.piece_at(pos.black.kings.into_iter().next().unwrap()).unwrap());
+    EvalTrace{
+        phase,
+        full_phase,
+        material: TermScore::from_counts(
+            material(&pos.white, params, taper),
+            material(&pos.black, params, taper),
+            params.material_scale,
+        ),
+        virtual_material: TermScore::from_counts(
+            material(&virt.white, params, taper),
+            material(&virt.black, params, taper),
+            params.virtual_material_scale,
+        ),
+        piece_squares: TermScore::from_counts(piece_happiness.white, piece_happiness.black, params.positional_scale),
+        king_mobility: TermScore::from_counts(
+            king_mobility(&virt.white, pos.white.kings),
+            king_mobility(&virt.black, pos.black.kings),
+            params.positional_scale,
+        ),
+        king_activity: TermScore::from_counts(
+            king_activity(position::Color::White),
+            king_activity(position::Color::Black),
+            params.positional_scale,
+        ),
+        pawn_advancement: TermScore::from_counts(
+            pawn_advancement(position::Color::White),
+            pawn_advancement(position::Color::Black),
+            params.positional_scale,
+        ),
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    sign * (material_evaluation * 10000 + virt_evaluation * 1000 + piece_happiness * 100)
+    #[test]
+    fn partial_parameter_files_keep_the_other_defaults() {
+        let params = EvalParams::from_toml("
+            material_scale = 5000
+
+            [piece_values.knight]
+            middlegame = 4
+
+            [lone_king_activity]
+            endgame = 60
+        ").expect("the file is valid");
+
+        let mut expected = EvalParams{ material_scale: 5000, ..EvalParams::default() };
+        expected.piece_values.knight.middlegame = 4;
+        expected.lone_king_activity.endgame = 60;
+        assert_eq!(params, expected);
+
+        assert!(EvalParams::from_toml("[piece_values.knight]\nopening = 4").is_err());
+        assert!(EvalParams::from_toml("[phase_weights]\nqueen = -20").is_err());
+        assert!(EvalParams::from_toml("phase_weights = { pawn = 0, knight = 0, bishop = 0, rook = 0, queen = 0 }").is_err());
+    }
 }
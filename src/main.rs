@@ -1,16 +1,32 @@
+mod attacks;
 mod position;
 mod evaluation;
 mod human_player;
 mod moves;
+mod notation;
+mod record;
+mod search_thread;
+mod transposition;
+mod tuning;
+mod uci;
+mod xboard;
+mod zobrist;
 
+use std::collections::HashMap;
 use human_player::HumanPlayer;
 
 trait Player {
     fn suggest_move(&mut self) -> moves::Move;
     fn input_move(&mut self, mov: moves::Move);
+    /// how the player is named in game records.
+    fn name(&self) -> String;
 }
 
 impl Player for moves::Engine {
+    fn name(&self) -> String {
+        "lh-chess".to_string()
+    }
+
     fn input_move(&mut self, mov: moves::Move) {
         self.input_move(mov);
     }
@@ -24,6 +40,10 @@ struct Game<'white, 'black> {
     white: &'white mut dyn Player,
     black: &'black mut dyn Player,
     game_history: Vec<position::Position>,
+    /// the moves played, the one at index `i` leading from `game_history[i]` to `game_history[i + 1]`.
+    moves: Vec<moves::Move>,
+    /// how many times each position key occurs in `game_history`.
+    repetitions: HashMap<u64, usize>,
 }
 
 enum GameState {
@@ -33,37 +53,33 @@ enum GameState {
     CheckMate,
 }
 
-impl Game<'_, '_> {
+impl<'white, 'black> Game<'white, 'black> {
+    fn new(white: &'white mut dyn Player, black: &'black mut dyn Player, pos: position::Position) -> Self {
+        Self {
+            white,
+            black,
+            repetitions: HashMap::from([(pos.key(), 1)]),
+            game_history: vec![pos],
+            moves: vec![],
+        }
+    }
+
     fn current_position(&self) -> &position::Position {
         self.game_history.last().expect("game has positions")
     }
 
     fn classify_position(&self) -> GameState {
-        if self.game_history[0..self.game_history.len() - 1]
-            .iter()
-            .filter(|&pos| pos == self.current_position())
-            .count() >= 3 {
+        if self.repetitions[&self.current_position().key()] > 3 {
             return GameState::DrawByRepetition;
         }
 
-        let mut mirror_position = self.current_position().clone();
-        mirror_position.turn = !mirror_position.turn;
-
-        let king_is_in_check = moves::moves(&mirror_position)
-            .iter()
-            .any(|&mov| mov.captured_piece == Some(position::Piece::King));
+        let king_is_in_check = moves::in_check(self.current_position());
+        let has_legal_moves = !moves::legal_moves(self.current_position()).is_empty();
 
-        let king_capture_is_forced = moves::moves(self.current_position())
-            .iter()
-            .all(|&mov| 
-                 moves::moves(&moves::apply_move(self.current_position(), mov))
-                     .iter()
-                     .any(|&mov| mov.captured_piece == Some(position::Piece::King)));
-
-        match (king_is_in_check, king_capture_is_forced) { 
-            (true,  true) => GameState::CheckMate,
-            (false, true) => GameState::StaleMate,
-            _             => GameState::Ongoing,
+        match (king_is_in_check, has_legal_moves) {
+            (true,  false) => GameState::CheckMate,
+            (false, false) => GameState::StaleMate,
+            _              => GameState::Ongoing,
         }
     }
 
@@ -75,16 +91,15 @@ impl Game<'_, '_> {
 
         let position_after_move = moves::apply_move(self.current_position(), mov);
 
-        match self.current_position().turn {
-            position::Color::White => println!("{}", mov),
-            position::Color::Black => println!("{:#}", mov),
-        }
+        println!("{} ({})", format_move(self.current_position(), mov), notation::san(self.current_position(), mov));
 
         println!("{position_after_move}");
 
         self.white.input_move(mov);
         self.black.input_move(mov);
+        *self.repetitions.entry(position_after_move.key()).or_default() += 1;
         self.game_history.push(position_after_move);
+        self.moves.push(mov);
 
         match self.classify_position() {
             GameState::CheckMate => {
@@ -103,24 +118,67 @@ impl Game<'_, '_> {
         }
     }
 
-    fn play_full_game(&mut self) {
+    /// Plays until the game ends, then writes its record to `record_path`, if given.
+    fn play_full_game(&mut self, record_path: Option<&str>) -> Result<(), String> {
         while self.play_turn() {}
-    }
-}
 
-fn main() -> Result<(), String> {
+        if let Some(path) = record_path {
+            std::fs::write(path, self.record().to_string()).map_err(|err| format!("cannot write {path}: {err}"))?;
+        }
+        Ok(())
+    }
 
-    if std::env::args().any(|arg| arg == "legal-moves") {
-        let lines = std::io::stdin().lines().map(|line| line.ok().unwrap()).collect::<Vec<_>>();
-        let board = lines.join("\n");
-        let start_position = position::Position::try_from(&*board)
-            .map_err(|square| format!("parse error at {square}"))?;
+    fn record(&self) -> record::GameRecord {
+        let mut result = record::GameRecord::new(&self.game_history[0]);
+        result.set_tag("Event", "lh-chess game");
+        result.set_tag("Site", "lh-chess");
+        result.set_tag("Date", &record::today());
+        result.set_tag("White", &self.white.name());
+        result.set_tag("Black", &self.black.name());
+        result.moves = self.moves
+            .iter()
+            .map(|&mov| record::RecordedMove{ mov, comment: None })
+            .collect();
 
-        for mov in moves::moves(&start_position) {
-            println!("{mov}");
+        let (game_result, reason) = match self.classify_position() {
+            GameState::CheckMate => match self.current_position().turn {
+                position::Color::White => (record::GameResult::BlackWins, Some("checkmate")),
+                position::Color::Black => (record::GameResult::WhiteWins, Some("checkmate")),
+            },
+            GameState::StaleMate => (record::GameResult::Draw, Some("stalemate")),
+            GameState::DrawByRepetition => (record::GameResult::Draw, Some("draw by repetition")),
+            GameState::Ongoing => (record::GameResult::Ongoing, None),
+        };
+        result.result = game_result;
+        if let Some(last) = result.moves.last_mut() {
+            last.comment = reason.map(str::to_string);
         }
+        result
+    }
+}
+
+/// Reads a position from stdin, either in the one-line notation or as an 8-line grid.
+fn read_position() -> Result<position::Position, String> {
+    let lines = std::io::stdin().lines().map(|line| line.ok().unwrap()).collect::<Vec<_>>();
+    let lines = lines.iter().map(|line| line.trim()).filter(|line| !line.is_empty()).collect::<Vec<_>>();
+    let strictness = if std::env::args().any(|arg| arg == "--lenient") {
+        position::Strictness::Lenient
     } else {
-        let pos = position::Position::try_from(
+        position::Strictness::Strict
+    };
+
+    let pos = position::Position::parse(&lines.join("\n"), strictness)
+        .map_err(|err| format!("invalid position: {err}"))?;
+
+    for violation in pos.validate() {
+        eprintln!("warning: {violation}");
+    }
+
+    Ok(pos)
+}
+
+fn start_position() -> position::Position {
+    position::Position::try_from(
 "rnbqkbnr
 pppppppp
 ........
@@ -128,14 +186,137 @@ pppppppp
 ........
 ........
 PPPPPPPP
-RNBQKBNR").expect("hardcoded position is valid");
+RNBQKBNR").expect("hardcoded position is valid")
+}
 
-        let mut game = Game{
-            white: &mut HumanPlayer::from_position(&pos),
-            black: &mut moves::Engine::from_position(&pos, 4),
-            game_history: vec![pos],
-        };
-        game.play_full_game();
+/// Parses the value following `name` on the command line, if `name` is given.
+fn option_value<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let Some(i) = args.iter().position(|arg| arg == name) else { return Ok(None) };
+    args.get(i + 1)
+        .and_then(|value| value.parse().ok())
+        .map(Some)
+        .ok_or(format!("{name} needs a valid value"))
+}
+
+/// Reads the evaluation weights from the file given with `--params`, or uses the default ones.
+fn read_eval_params() -> Result<evaluation::EvalParams, String> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let Some(i) = args.iter().position(|arg| arg == "--params") else {
+        return Ok(evaluation::EvalParams::default());
+    };
+
+    let path = args.get(i + 1).ok_or("--params needs a file name")?;
+    let text = std::fs::read_to_string(path).map_err(|err| format!("cannot read {path}: {err}"))?;
+    evaluation::EvalParams::from_toml(&text).map_err(|err| format!("invalid parameters in {path}: {err}"))
+}
+
+/// Moves are written with lowercase pieces for the side to move when it is black.
+fn format_move(pos: &position::Position, mov: moves::Move) -> String {
+    match pos.turn {
+        position::Color::White => format!("{mov}"),
+        position::Color::Black => format!("{mov:#}"),
+    }
+}
+
+fn main() -> Result<(), String> {
+    let args = std::env::args().collect::<Vec<_>>();
+
+    match args.get(1).map(String::as_str) {
+        Some("legal-moves") => {
+            let start_position = read_position()?;
+            let san = args.iter().any(|arg| arg == "--san");
+            for mov in moves::legal_moves(&start_position) {
+                if san {
+                    println!("{}", notation::san(&start_position, mov));
+                } else {
+                    println!("{}", format_move(&start_position, mov));
+                }
+            }
+        },
+        Some("attacks") => {
+            let start_position = read_position()?;
+            for color in [position::Color::White, position::Color::Black] {
+                println!("{color} attacks:\n{}", start_position.attacks(color));
+            }
+        },
+        Some("eval") => {
+            let start_position = read_position()?;
+            let params = read_eval_params()?;
+            let trace = evaluation::trace(&start_position, &params);
+            print!("{trace}");
+            println!("\nscore for {}: {}", start_position.turn, trace.score(start_position.turn));
+        },
+        Some("params") => {
+            print!("{}", read_eval_params()?.to_toml());
+        },
+        Some("tune") => {
+            let defaults = tuning::TuningOptions::default();
+            let options = tuning::TuningOptions{
+                games: option_value("--games")?.unwrap_or(defaults.games),
+                depth: option_value("--depth")?.unwrap_or(defaults.depth),
+                iterations: option_value("--iterations")?.unwrap_or(defaults.iterations),
+                seed: option_value("--seed")?.unwrap_or(defaults.seed),
+                ..defaults
+            };
+            if options.depth < 1 {
+                return Err("--depth needs to be at least 1".to_string());
+            }
+            let params = read_eval_params()?;
+
+            let samples = tuning::generate_samples(&start_position(), &params, &options);
+            let tuned = tuning::tune(&samples, &params, &options);
+
+            match option_value::<String>("--output")? {
+                Some(path) => std::fs::write(&path, tuned.to_toml()).map_err(|err| format!("cannot write {path}: {err}"))?,
+                None => print!("{}", tuned.to_toml()),
+            }
+        },
+        Some("uci") => {
+            uci::run(read_eval_params()?);
+        },
+        Some("xboard") => {
+            xboard::run(read_eval_params()?);
+        },
+        Some("replay") => {
+            let text = std::io::read_to_string(std::io::stdin()).map_err(|err| format!("cannot read the record: {err}"))?;
+            let game = text.parse::<record::GameRecord>().map_err(|err| format!("invalid record: {err}"))?;
+            let positions = game.positions();
+            println!("{:#}", positions[0]);
+            for (i, recorded) in game.moves.iter().enumerate() {
+                println!("{} {:#}", notation::san(&positions[i], recorded.mov), positions[i + 1]);
+            }
+            println!("{}", game.result);
+        },
+        Some("perft") => {
+            let depth = args
+                .get(2)
+                .and_then(|depth| depth.parse().ok())
+                .ok_or("usage: lh-chess perft <depth> < position")?;
+            let start_position = read_position()?;
+
+            let divide = moves::divide(&start_position, depth);
+            for &(mov, count) in &divide {
+                println!("{}: {count}", format_move(&start_position, mov));
+            }
+            let total = if depth == 0 { 1 } else { divide.iter().map(|&(_, count)| count).sum() };
+            println!("\ntotal: {total}");
+        },
+        _ => {
+            let pos = start_position();
+
+            let mut white = HumanPlayer::from_position(&pos);
+            let mut black = moves::Engine::from_position(&pos, 4);
+            black.set_eval_params(read_eval_params()?);
+            black.set_info_callback(|info| {
+                let pv = info.pv.iter().map(|mov| mov.to_string()).collect::<Vec<_>>().join(" ");
+                eprintln!("depth {} ({}) score {} nodes {} ({} n/s) in {:.2?}: {}",
+                    info.depth, info.seldepth, info.score, info.nodes, info.nps, info.elapsed, pv);
+            });
+            let record_path = option_value::<String>("--record")?;
+            let mut game = Game::new(&mut white, &mut black, pos);
+            game.play_full_game(record_path.as_deref())?;
+        },
     }
 
     Ok(())
@@ -1,6 +1,11 @@
-use crate::position::{Square, SquareDiff, Position, Color, BitBoard, Piece};
-use crate::evaluation;
+use crate::attacks;
+use crate::position::{Square, Position, Color, BitBoard, Piece, Undo};
+use crate::evaluation::{self, EvalParams};
+use crate::transposition::{Bound, Entry, TranspositionTable};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Move {
@@ -43,18 +48,10 @@ impl std::fmt::Display for Move {
 }
 
 pub fn moves(pos: &Position) -> Vec<Move> {
-
-    let virt = pos.to_virtual_position();
-
-    let own_forward = match pos.turn {
-        Color::White =>  1,
-        Color::Black => -1,
-    };
-
-    let own_virtual_pieces = match pos.turn {
-        Color::Black => virt.black,
-        Color::White => virt.white,
-    };
+    let own_virtual_pieces = pos.virtual_pieces(pos.turn);
+    let own = pos.player(pos.turn).all().0;
+    let enemy = pos.player(!pos.turn).all().0;
+    let occupied = BitBoard(own | enemy);
 
     let own_promotion_rank = match pos.turn {
         Color::White => 7,
@@ -62,266 +59,605 @@ pub fn moves(pos: &Position) -> Vec<Move> {
     };
 
     let mut result = vec![];
-    let mut emit_move = |from, to| {
+    let mut emit_moves = |from: Square, targets: BitBoard| {
         let moved_piece = pos.player(pos.turn).piece_at(from).expect("has moving piece");
-        let captured_piece = pos.player(!pos.turn).piece_at(to);
+        for to in targets {
+            let captured_piece = pos.player(!pos.turn).piece_at(to);
 
-        if to.rank() == own_promotion_rank && moved_piece == Piece::Pawn {
-            for promote_to in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
-                result.push(Move{ from, to, moved_piece, captured_piece, promote_to: Some(promote_to) });
+            if to.rank() == own_promotion_rank && moved_piece == Piece::Pawn {
+                for promote_to in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+                    result.push(Move{ from, to, moved_piece, captured_piece, promote_to: Some(promote_to) });
+                }
+            } else {
+                result.push(Move{ from, to, moved_piece, captured_piece, promote_to: None });
             }
-        } else {
-            result.push(Move{ from, to, moved_piece, captured_piece, promote_to: None });
         }
     };
 
     for start_square in own_virtual_pieces.pawns {
-
-        // forward move
-        if let Some(landing_square) = (start_square + SquareDiff{rank_diff: own_forward, file_diff: 0}) {
-            if !pos.all().has(landing_square) { 
-                emit_move(start_square, landing_square);
-            }
-        }
-
-        for (rank_diff, file_diff) in [
-            (own_forward, -1), (own_forward,  1),
-        ] {
-            let Some(landing_square) = (start_square + SquareDiff{rank_diff, file_diff}) else { continue };
-            if pos.player(pos.turn).all().has(landing_square) { continue }   // self-capture is not allowed.
-            if !pos.player(!pos.turn).all().has(landing_square) { continue } // diagonal move must be a capture.
-            emit_move(start_square, landing_square);
-        }
+        let bit = 1u64 << start_square.to_bit_position();
+        // forward move, which only lands on an empty square.
+        let push = match pos.turn {
+            Color::White => bit << 8,
+            Color::Black => bit >> 8,
+        } & !occupied.0;
+        // diagonal move, which must be a capture.
+        let captures = attacks::pawn_attacks(pos.turn, start_square).0 & enemy;
+        emit_moves(start_square, BitBoard(push | captures));
     }
 
     for start_square in own_virtual_pieces.knights {
-        for (rank_diff, file_diff) in [
-            /*    */  (-2, -1), /*    */  (-2,  1), /*    */
-            (-1, -2), /*    */  /*    */  /*    */  (-1,  2),
-            /*    */  /*    */  /*    */  /*    */  /*    */ 
-            ( 1, -2), /*    */  /*    */  /*    */  ( 1,  2),
-            /*    */  ( 2, -1), /*    */  ( 2,  1), /*    */
-        ] {
-            let Some(landing_square) = (start_square + SquareDiff{rank_diff, file_diff}) else { continue };
-            if pos.player(pos.turn).all().has(landing_square) { continue } // self-capture is not allowed.
-            emit_move(start_square, landing_square);
-        }
+        emit_moves(start_square, BitBoard(attacks::knight_attacks(start_square).0 & !own));
     }
 
-    for start_square in BitBoard(own_virtual_pieces.bishops.0 | own_virtual_pieces.queens.0) {
-        for (rank_diff, file_diff) in [
-            (-1, -1), /*    */  (-1,  1),
-            /*    */  /*    */  /*    */
-            ( 1, -1), /*    */  ( 1,  1),
-        ] {
-            let direction = SquareDiff{rank_diff, file_diff};
-            for step in 1..7 {
-                let Some(landing_square) = start_square + direction * step else { break };
-                if pos.player(pos.turn).all().has(landing_square) { break }
-                emit_move(start_square, landing_square);
-                if pos.player(!pos.turn).all().has(landing_square) { break }
-            }
-        }
+    for start_square in own_virtual_pieces.bishops {
+        emit_moves(start_square, BitBoard(attacks::bishop_attacks(start_square, occupied).0 & !own));
     }
 
-    for start_square in BitBoard(own_virtual_pieces.rooks.0 | own_virtual_pieces.queens.0) {
-        for (rank_diff, file_diff) in [
-            /*    */  (-1,  0), /*    */ 
-            ( 0, -1), /*    */  ( 0,  1),
-            /*    */  ( 1,  0), /*    */ 
-        ] {
-            let direction = SquareDiff{rank_diff, file_diff};
-            for step in 1..7 {
-                let Some(landing_square) = start_square + direction * step else { break };
-                if pos.player(pos.turn).all().has(landing_square) { break }
-                emit_move(start_square, landing_square);
-                if pos.player(!pos.turn).all().has(landing_square) { break }
-            }
-        }
+    for start_square in own_virtual_pieces.rooks {
+        emit_moves(start_square, BitBoard(attacks::rook_attacks(start_square, occupied).0 & !own));
+    }
+
+    for start_square in own_virtual_pieces.queens {
+        emit_moves(start_square, BitBoard(attacks::queen_attacks(start_square, occupied).0 & !own));
     }
 
     for start_square in own_virtual_pieces.kings {
-        for (rank_diff, file_diff) in [
-            (-1, -1), (-1,  0), (-1, 1),
-            ( 0, -1), /*    */  ( 0, 1),
-            ( 1, -1), ( 1,  0), ( 1, 1),
-        ] {
-            let Some(landing_square) = (start_square + SquareDiff{rank_diff, file_diff}) else { continue };
-            if pos.player(pos.turn).all().has(landing_square) { continue }
-            emit_move(start_square, landing_square);
-        }
+        emit_moves(start_square, BitBoard(attacks::king_attacks(start_square).0 & !own));
     }
 
     result
 }
 
 
+/// Counts the legal move sequences of the given length, see [`legal_moves`].
+pub fn perft(pos: &Position, depth: usize) -> u64 {
+    fn perft_in_place(pos: &mut Position, depth: usize) -> u64 {
+        match depth {
+            0 => 1,
+            1 => legal_moves(pos).len() as u64,
+            _ => legal_moves(pos)
+                .into_iter()
+                .map(|mov| {
+                    let undo = pos.make_move(mov);
+                    let count = perft_in_place(pos, depth - 1);
+                    pos.unmake_move(mov, undo);
+                    count
+                })
+                .sum(),
+        }
+    }
+    perft_in_place(&mut pos.clone(), depth)
+}
+
+/// Like [`perft`], but split by the first move.
+pub fn divide(pos: &Position, depth: usize) -> Vec<(Move, u64)> {
+    if depth == 0 { return vec![] }
+    legal_moves(pos)
+        .into_iter()
+        .map(|mov| (mov, perft(&apply_move(pos, mov), depth - 1)))
+        .collect()
+}
+
+/// Whether the king of the side to move could be captured if it were the opponent's turn.
+pub fn in_check(pos: &Position) -> bool {
+    pos.king_attacked(pos.turn)
+}
+
+/// The moves from [`moves`] that do not leave the mover's king capturable.
+pub fn legal_moves(pos: &Position) -> Vec<Move> {
+    let mut scratch = pos.clone();
+    moves(pos)
+        .into_iter()
+        .filter(|&mov| {
+            let undo = scratch.make_move(mov);
+            let legal = !scratch.king_attacked(pos.turn);
+            scratch.unmake_move(mov, undo);
+            legal
+        })
+        .collect()
+}
+
 pub fn apply_move(pos: &Position, mov: Move) -> Position {
     let mut result = pos.clone();
-    result.turn = !result.turn;
+    result.make_move(mov);
+    result
+}
 
-    result.mut_player(pos.turn).mut_pieces(mov.moved_piece).unset(mov.from);
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Clock {
+    pub remaining: Duration,
+    pub increment: Duration,
+}
+
+/// When the engine should stop searching; any limit that is reached ends the search.
+/// With no limits at all the search only ends at the maximum depth.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub max_depth: Option<isize>,
+    pub max_nodes: Option<u64>,
+    pub move_time: Option<Duration>,
+    /// the time left on the engine's clock, from which a share is allotted to this move.
+    pub clock: Option<Clock>,
+}
 
-    if let Some(piece) = mov.captured_piece {
-        result.mut_player(!pos.turn).mut_pieces(piece).unset(mov.to);
+impl SearchLimits {
+    pub fn depth(depth: isize) -> Self {
+        Self{ max_depth: Some(depth), ..Self::default() }
     }
 
-    if let Some(piece) = mov.promote_to {
-        result.mut_player(pos.turn).mut_pieces(piece).set(mov.to);
-    } else {
-        result.mut_player(pos.turn).mut_pieces(mov.moved_piece).set(mov.to);
+    /// Returns the time after which the search must stop, and the time after which
+    /// no new iteration should be started.
+    fn time_budget(&self) -> (Option<Duration>, Option<Duration>) {
+        let clock_budget = self.clock.map(|Clock{ remaining, increment }| {
+            let budget = remaining / 30 + increment * 3 / 4;
+            budget.min(remaining * 4 / 5)
+        });
+
+        let hard = match (self.move_time, clock_budget) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        // an iteration takes several times as long as the previous one,
+        // so it is not worth starting one with less than half of the budget left.
+        let soft = clock_budget.map(|budget| budget / 2);
+
+        (hard, soft)
     }
+}
 
-    result
+/// A rough value of each piece for ordering moves; the evaluation uses [`EvalParams::piece_values`].
+fn piece_value(piece: Piece) -> isize {
+    match piece {
+        Piece::Pawn   => 1,
+        Piece::Knight => 3,
+        Piece::Bishop => 3,
+        Piece::Rook   => 5,
+        Piece::Queen  => 9,
+        Piece::King   => 100,
+    }
+}
+
+
+/// Progress of the search, reported after every completed iteration.
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: isize,
+    pub seldepth: isize,
+    pub nodes: u64,
+    /// nodes per second.
+    pub nps: u64,
+    pub elapsed: Duration,
+    /// from the perspective of the side to move, see [`Engine::is_mate_score`].
+    pub score: isize,
+    /// the line the engine expects to be played, starting with its best move.
+    pub pv: Vec<Move>,
 }
 
+pub type InfoCallback = Box<dyn FnMut(&SearchInfo) + Send>;
+
 pub struct Engine {
-    game_history: Vec<Position>,
-    recursion_depth_soft_cap: isize,
-    recursion_depth_hard_cap: isize,
-    // evaluation_cache: HashMap<Position, (isize, isize)>,
+    position: Position,
+    /// the moves that led to `position`, with what is needed to take them back.
+    game_history: Vec<(Move, Undo)>,
+    /// how many times each position key occurs in the game.
+    repetitions: HashMap<u64, usize>,
+    limits: SearchLimits,
+    transposition_table: TranspositionTable,
+    eval_params: EvalParams,
+    delta_pruning: bool,
+    info_callback: Option<InfoCallback>,
+    nodes: u64,
+    /// the deepest ply reached in the current iteration, including the quiescence search.
+    seldepth: isize,
+    deadline: Option<Instant>,
+    /// set from another thread to end the search early; the engine never clears it.
+    stop_signal: Arc<AtomicBool>,
+    stopped: bool,
 }
 
 
 impl Engine {
     pub fn from_position(pos: &Position, depth: isize) -> Self {
         Engine{
-            game_history: vec![pos.clone()],
-            recursion_depth_soft_cap: depth,
-            recursion_depth_hard_cap: depth + 3,
-            // evaluation_cache: HashMap::new(),
+            position: pos.clone(),
+            game_history: vec![],
+            repetitions: HashMap::from([(pos.key(), 1)]),
+            limits: SearchLimits::depth(depth),
+            transposition_table: TranspositionTable::with_size_mb(TranspositionTable::DEFAULT_SIZE_MB),
+            eval_params: EvalParams::default(),
+            delta_pruning: true,
+            info_callback: None,
+            nodes: 0,
+            seldepth: 0,
+            deadline: None,
+            stop_signal: Arc::new(AtomicBool::new(false)),
+            stopped: false,
         }
     }
 
-    pub fn new() -> Self {
-        Engine{
-            game_history: vec![Position::try_from(
-"rnbqkbnr
-pppppppp
-........
-........
-........
-........
-PPPPPPPP
-RNBQKBNR").expect("hardcoded position is valid")],
-            recursion_depth_soft_cap: 5,
-            recursion_depth_hard_cap: 8,
-            // evaluation_cache: HashMap::new(),
-        }
+    pub fn set_limits(&mut self, limits: SearchLimits) {
+        self.limits = limits;
     }
 
-    pub fn suggest_and_play_move(&mut self) -> Option<(Move, isize)> {
-        match self.suggest_move() {
-            res @ Some((mov, _)) => { self.input_move(mov); res},
-            res => res
-        }
+    /// Sets a function that is called with the progress of the search after every iteration.
+    pub fn set_info_callback(&mut self, callback: impl FnMut(&SearchInfo) + Send + 'static) {
+        self.info_callback = Some(Box::new(callback));
+    }
+
+    /// Replaces the weights of the evaluation; scores stored with the old ones are forgotten.
+    pub fn set_eval_params(&mut self, params: EvalParams) {
+        self.eval_params = params;
+        self.transposition_table.clear();
+    }
+
+    /// Enables or disables skipping hopeless captures in the quiescence search.
+    pub fn set_delta_pruning(&mut self, enabled: bool) {
+        self.delta_pruning = enabled;
+    }
+
+    /// Replaces the transposition table with an empty one of the given size.
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.transposition_table = TranspositionTable::with_size_mb(megabytes);
+    }
+
+    /// Forgets the results of previous searches.
+    pub fn clear_hash(&mut self) {
+        self.transposition_table.clear();
+    }
+
+    /// A flag that stops the current search, and every later one, while it is set.
+    /// The search checks it as often as the deadline, so it can be set from another thread.
+    pub fn stop_signal(&self) -> Arc<AtomicBool> {
+        self.stop_signal.clone()
+    }
+
+    /// Starts a new game from `pos`, keeping the settings and the transposition table.
+    pub fn set_position(&mut self, pos: &Position) {
+        self.position = pos.clone();
+        self.game_history.clear();
+        self.repetitions = HashMap::from([(pos.key(), 1)]);
     }
 
     pub fn roll_back(&mut self) {
-        if self.game_history.len() > 1 {
-            self.game_history.pop();
+        if let Some((mov, undo)) = self.game_history.pop() {
+            let key = self.position.key();
+            match self.repetitions.get_mut(&key) {
+                Some(1) => { self.repetitions.remove(&key); },
+                Some(count) => *count -= 1,
+                None => unreachable!("every position in the history is counted"),
+            }
+            self.position.unmake_move(mov, undo);
         }
     }
 
     pub fn input_move(&mut self, mov: Move) {
-        self.game_history.push(apply_move(self.current_position(), mov));
+        let undo = self.position.make_move(mov);
+        *self.repetitions.entry(self.position.key()).or_default() += 1;
+        self.game_history.push((mov, undo));
     }
 
     pub fn current_position(&self) -> &Position {
-        self.game_history.last().expect("a valid engine always has some state")
+        &self.position
     }
 
-    const EVAL_MAX : isize = isize::MAX / 2;
-    const EVAL_MIN : isize = isize::MIN / 2;
+    /// Score of delivering checkmate, minus the number of plies it takes,
+    /// so that the quickest mate is preferred.
+    pub const MATE_SCORE: isize = 1 << 40;
+    const MAX_PLY: isize = 1024;
+
+    const SCORE_MAX: isize = Self::MATE_SCORE + 1;
 
+    /// in pawns, on top of the value of the captured piece.
+    const DELTA_MARGIN: isize = 3;
+
+    pub fn is_mate_score(score: isize) -> bool {
+        score.abs() > Self::MATE_SCORE - Self::MAX_PLY
+    }
+
+    /// Searches with increasing depth until one of the [`SearchLimits`] is reached,
+    /// and returns the best move of the deepest completed iteration.
     pub fn suggest_move(&mut self) -> Option<(Move, isize)> {
-        // if self.evaluation_cache.len() > 1_000_000_000 {
-        //     self.evaluation_cache.clear()
-        // }
-        self.suggest_move_internal(0, Self::EVAL_MAX)
+        let start = Instant::now();
+        let (hard_budget, soft_budget) = self.limits.time_budget();
+        self.deadline = hard_budget.map(|budget| start + budget);
+        self.nodes = 0;
+        self.stopped = false;
+
+        let mut best = None;
+        for depth in 1..=self.limits.max_depth.unwrap_or(Self::MAX_PLY) {
+            self.seldepth = 0;
+            let mut pv = vec![];
+            let result = self.search_root(depth, &mut pv);
+            if self.stopped {
+                // a partial iteration is only used if there is nothing better.
+                best = best.or(result);
+                break;
+            }
+            best = result;
+
+            if let (Some((_, score)), Some(callback)) = (best, &mut self.info_callback) {
+                let elapsed = start.elapsed();
+                callback(&SearchInfo{
+                    depth,
+                    seldepth: self.seldepth,
+                    nodes: self.nodes,
+                    nps: (self.nodes as f64 / elapsed.as_secs_f64().max(1e-6)) as u64,
+                    elapsed,
+                    score,
+                    pv,
+                });
+            }
+
+            match best {
+                None => break,
+                Some((_, score)) if Self::is_mate_score(score) => break,
+                _ => (),
+            }
+            if soft_budget.is_some_and(|budget| start.elapsed() > budget) {
+                break;
+            }
+        }
+
+        best
     }
 
-    fn suggest_move_internal(&mut self, current_depth: isize, prune_threshold: isize) -> Option<(Move, isize)> {
-        // stalemate condition
-        if self.game_history.iter().filter(|&pos| pos == self.current_position()).count() > 2 {
-            return None;
+    fn search_root(&mut self, depth: isize, pv: &mut Vec<Move>) -> Option<(Move, isize)> {
+        let key = self.position.key();
+        let mut root_moves = legal_moves(self.current_position());
+        Self::order_moves(&mut root_moves);
+
+        // the best move of the previous iteration is searched first
+        if let Some(Entry{ best_move: Some(mov), .. }) = self.transposition_table.probe(key) {
+            if let Some(i) = root_moves.iter().position(|m| m == mov) {
+                root_moves[..=i].rotate_right(1);
+            }
         }
 
-        let current_position = self.current_position().clone();
-        let mut legal_moves = moves(&current_position);
+        let mut alpha = -Self::SCORE_MAX;
+        let beta = Self::SCORE_MAX;
+        let mut best = None;
+        let mut child_pv = vec![];
 
-        if current_depth == 0 { print!("\nanalyze {} legal moves\n", legal_moves.len())}
-        if current_depth == 1 { 
-            eprint!(".");
+        for (i, &mov) in root_moves.iter().enumerate() {
+            let score = self.search_child(mov, i == 0, depth - 1, 1, alpha, beta, &mut child_pv);
+            if self.stopped {
+                break;
+            }
+            if best.is_none() || score > alpha {
+                alpha = score;
+                best = Some((mov, score));
+                Self::update_pv(pv, mov, &child_pv);
+            }
         }
 
-        // if can capture king, immediately return
-        if let Some(&mov) = legal_moves.iter().find(|mov| mov.captured_piece == Some(Piece::King)) {
-            return Some((mov,Self::EVAL_MAX));
+        if let (Some((mov, score)), false) = (best, self.stopped) {
+            self.store(key, depth, 0, score, Bound::Exact, Some(mov));
         }
 
-        // look forward at half depth to sort the moves
-        if current_depth < self.recursion_depth_soft_cap {
-            legal_moves.sort_by_key(|&mov| {
-                self.input_move(mov);
-                let new_depth = (current_depth + 1) * 2;
-                let eval = self
-                    .suggest_move_internal(new_depth, Self::EVAL_MAX)
-                    .map(|(_, eval)| eval)
-                    .unwrap_or(0);
-
-                self.roll_back();
-                eval
-            });
+        best
+    }
+
+    fn check_limits(&mut self) {
+        if self.limits.max_nodes.is_some_and(|max_nodes| self.nodes >= max_nodes) {
+            self.stopped = true;
+        }
+        if self.nodes.is_multiple_of(1024) {
+            let past_deadline = self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if past_deadline || self.stop_signal.load(Ordering::Relaxed) {
+                self.stopped = true;
+            }
         }
+    }
 
+    fn update_pv(pv: &mut Vec<Move>, mov: Move, child_pv: &[Move]) {
+        pv.clear();
+        pv.push(mov);
+        pv.extend_from_slice(child_pv);
+    }
 
-        let result = legal_moves
-            .iter()
-            .enumerate()
-            .scan(None, |best, (i, &mov)| {
-                let old_advantage = best.map(|(_, advantage)| advantage).unwrap_or(Self::EVAL_MIN);
-                if old_advantage >= prune_threshold { return None }
-
-                let shallow = current_depth < self.recursion_depth_soft_cap;
-                let deepening = current_depth < self.recursion_depth_hard_cap;
-                let capturing = mov.captured_piece.is_some();
-
-                let mut advantage = if shallow || (deepening && capturing) {
-                    self.input_move(mov);
-                    let deepening = if i < legal_moves.len() / 3 { 1 } else if i < 3 * legal_moves.len() / 2 { 2 } else { 3 };
-                    let adv = self.suggest_move_internal(current_depth + deepening, - old_advantage)
-                        .map(|(_, eval)| eval)
-                        .unwrap_or(0);
-                    self.roll_back();
-                    - adv
-                } else {
-                    - evaluation::evaluate(&apply_move(&current_position, mov))
-                };
-
-                // advantage from a move far away in the future gets proportionally less defined
-                advantage = advantage / 4 * 3;
-
-                *best = match *best {
-                    None => Some((mov, advantage)),
-                    Some((_, old_advantage)) if old_advantage < advantage => Some((mov, advantage)),
-                    other => other
-                };
-                *best
-            })
-            .last();
-
-        // match result {
-        //     Some((_, advantage)) => {
-        //         match self.evaluation_cache.get(&current_position) {
-        //             Some((depth, _)) if *depth <= current_depth => (),
-        //             _ => { self.evaluation_cache.insert(current_position, (current_depth, advantage)); },
-        //         }
-        //     },
-        //     None => (),
-        // }
-
-        result
+    /// Plays `mov` and searches the resulting position, returning the score from the
+    /// perspective of the side that played it, and the expected continuation in `pv`.
+    ///
+    /// Apart from the first move of a node, moves are searched with a null window first,
+    /// and only re-searched with the full window when they turn out to be better than `alpha`.
+    #[allow(clippy::too_many_arguments)]
+    fn search_child(&mut self, mov: Move, first: bool, depth: isize, ply: isize, alpha: isize, beta: isize, pv: &mut Vec<Move>) -> isize {
+        self.input_move(mov);
+        let score = if first {
+            -self.negamax(depth, ply, -beta, -alpha, pv)
+        } else {
+            let score = -self.negamax(depth, ply, -alpha - 1, -alpha, pv);
+            if alpha < score && score < beta {
+                -self.negamax(depth, ply, -beta, -alpha, pv)
+            } else {
+                score
+            }
+        };
+        self.roll_back();
+        score
     }
-}
 
+    fn negamax(&mut self, depth: isize, ply: isize, mut alpha: isize, beta: isize, pv: &mut Vec<Move>) -> isize {
+        pv.clear();
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply);
+        self.check_limits();
+        if self.stopped {
+            return 0;
+        }
+
+        let key = self.position.key();
+
+        if self.repetitions[&key] > 2 {
+            return 0;
+        }
+
+        let tt_entry = self.transposition_table.probe(key).copied();
+        if let Some(entry) = tt_entry {
+            let score = Self::score_from_table(entry.score, ply);
+            let usable = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if entry.depth >= depth && usable {
+                return score;
+            }
+        }
+
+        if depth <= 0 {
+            return self.quiescence(ply, alpha, beta);
+        }
+
+        let mut legal_moves = legal_moves(&self.position);
+
+        if legal_moves.is_empty() {
+            return if in_check(&self.position) { -(Self::MATE_SCORE - ply) } else { 0 };
+        }
+
+        // the best move of an earlier search is tried first
+        let mut unordered = &mut legal_moves[..];
+        if let Some(Entry{ best_move: Some(mov), .. }) = tt_entry {
+            if let Some(i) = unordered.iter().position(|&m| m == mov) {
+                unordered[..=i].rotate_right(1);
+                unordered = &mut unordered[1..];
+            }
+        }
+        Self::order_moves(unordered);
+
+        let original_alpha = alpha;
+        let mut best: Option<(Move, isize)> = None;
+        let mut child_pv = vec![];
+
+        for (i, &mov) in legal_moves.iter().enumerate() {
+            let score = self.search_child(mov, i == 0, depth - 1, ply + 1, alpha, beta, &mut child_pv);
+            if self.stopped {
+                return 0;
+            }
+
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((mov, score));
+            }
+            if score > alpha {
+                Self::update_pv(pv, mov, &child_pv);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let Some((best_move, best_score)) = best else {
+            unreachable!("there is at least one legal move");
+        };
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.store(key, depth, ply, best_score, bound, Some(best_move));
+
+        best_score
+    }
+
+    /// Searches captures and promotions until the position is quiet, so that the
+    /// evaluation is never taken in the middle of an exchange.
+    ///
+    /// The side to move may "stand pat" and keep the static evaluation instead of capturing.
+    ///
+    /// Filtering out illegal captures up front would cost a move generation per capture,
+    /// so they are searched like the others and refuted by the king capture in the next call.
+    fn quiescence(&mut self, ply: isize, mut alpha: isize, beta: isize) -> isize {
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply);
+        self.check_limits();
+        if self.stopped {
+            return 0;
+        }
+
+        if self.repetitions[&self.position.key()] > 2 {
+            return 0;
+        }
+
+        let mut captures = moves(&self.position);
+
+        if captures.iter().any(|mov| mov.captured_piece == Some(Piece::King)) {
+            return Self::MATE_SCORE - ply;
+        }
+
+        let stand_pat = evaluation::evaluate(&self.position, &self.eval_params);
+        if stand_pat >= beta || ply >= Self::MAX_PLY {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        captures.retain(|mov| mov.captured_piece.is_some() || mov.promote_to.is_some());
+        Self::order_moves(&mut captures);
+
+        let mut best_score = stand_pat;
+
+        for mov in captures {
+            // a capture that can not bring the score back up to alpha, even with a
+            // generous margin for positional changes, is not worth searching.
+            if self.delta_pruning && mov.promote_to.is_none() {
+                let gain = mov.captured_piece.map_or(0, |piece| {
+                    let value = self.eval_params.piece_values.get(piece);
+                    value.middlegame.max(value.endgame)
+                });
+                if stand_pat + (gain + Self::DELTA_MARGIN) * self.eval_params.material_scale < alpha {
+                    continue;
+                }
+            }
+
+            self.input_move(mov);
+            let score = -self.quiescence(ply + 1, -beta, -alpha);
+            self.roll_back();
+
+            if self.stopped {
+                return 0;
+            }
+
+            best_score = best_score.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best_score
+    }
+
+    /// Captures first, most valuable victim first.
+    fn order_moves(moves: &mut [Move]) {
+        moves.sort_by_key(|mov| -mov.captured_piece.map_or(0, piece_value) * 10 + piece_value(mov.moved_piece));
+    }
+
+    fn store(&mut self, key: u64, depth: isize, ply: isize, score: isize, bound: Bound, best_move: Option<Move>) {
+        self.transposition_table.store(Entry{
+            key,
+            depth,
+            score: Self::score_to_table(score, ply),
+            bound,
+            best_move,
+        });
+    }
+
+    // mate scores are stored relative to the position rather than to the root,
+    // since the same position can be reached at different plies.
+    fn score_to_table(score: isize, ply: isize) -> isize {
+        if !Self::is_mate_score(score) { score }
+        else if score > 0 { score + ply }
+        else { score - ply }
+    }
+
+    fn score_from_table(score: isize, ply: isize) -> isize {
+        if !Self::is_mate_score(score) { score }
+        else if score > 0 { score - ply }
+        else { score + ply }
+    }
+}
@@ -0,0 +1,277 @@
+//! Reading and writing moves as text.
+//!
+//! Three notations are understood:
+//! - the long notation printed by [`Move`]'s `Display`, such as `pG5xQH5`: the piece, its square,
+//!   `x` and the captured piece for a capture, the destination and the promotion piece if any;
+//! - coordinate notation, as used by the GUI protocols, such as `g5h5` or `e7e8q`;
+//! - short algebraic notation, in which the letter is the piece the moved piece moves like,
+//!   such as `Nf3`, `exd5` or `Rad1`.
+
+use crate::moves::{self, Move};
+use crate::position::{Piece, Position, Square};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveParseError {
+    /// The text is not a move in any of the notations.
+    Malformed(String),
+    Illegal(String),
+    /// The move would be legal, but for leaving the king capturable.
+    LeavesKingInCheck(String),
+    /// More than one legal move fits the text.
+    Ambiguous { text: String, candidates: Vec<Move> },
+}
+
+impl std::fmt::Display for MoveParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(text) =>
+                f.write_fmt(format_args!("'{text}' is not a move")),
+            Self::Illegal(text) =>
+                f.write_fmt(format_args!("{text} is not a legal move in this position")),
+            Self::LeavesKingInCheck(text) =>
+                f.write_fmt(format_args!("{text} leaves the king hanging")),
+            Self::Ambiguous{text, candidates} => {
+                f.write_fmt(format_args!("{text} is ambiguous, it could be "))?;
+                for (i, &mov) in candidates.iter().enumerate() {
+                    if i > 0 { f.write_str(" or ")?; }
+                    f.write_str(&coordinate(mov))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::error::Error for MoveParseError {}
+
+/// Reads a piece letter in either case.
+fn piece_from_letter(c: u8) -> Option<Piece> {
+    match c.to_ascii_uppercase() {
+        b'P' => Some(Piece::Pawn),
+        b'N' => Some(Piece::Knight),
+        b'B' => Some(Piece::Bishop),
+        b'R' => Some(Piece::Rook),
+        b'Q' => Some(Piece::Queen),
+        b'K' => Some(Piece::King),
+        _    => None,
+    }
+}
+
+fn promotion_from_letter(c: u8) -> Option<Piece> {
+    piece_from_letter(c).filter(|piece| !matches!(piece, Piece::Pawn | Piece::King))
+}
+
+/// Reads a square with the file in either case, such as `G5` or `g5`.
+fn parse_square(s: &[u8]) -> Option<Square> {
+    match *s {
+        [file, rank @ b'1'..=b'8'] => match file.to_ascii_lowercase() {
+            file @ b'a'..=b'h' => Some(Square::new(rank - b'1', file - b'a')),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Reads the long notation without a position, so the move is only checked for its syntax.
+/// The case of the piece letters is not checked.
+impl std::str::FromStr for Move {
+    type Err = MoveParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || MoveParseError::Malformed(s.to_string());
+
+        let (&moved, rest) = s.as_bytes().split_first().ok_or_else(malformed)?;
+        let moved_piece = piece_from_letter(moved).ok_or_else(malformed)?;
+        let from = rest.get(..2).and_then(parse_square).ok_or_else(malformed)?;
+
+        let (captured_piece, rest) = match &rest[2..] {
+            [b'x', piece, rest @ ..] => (Some(piece_from_letter(*piece).ok_or_else(malformed)?), rest),
+            rest => (None, rest),
+        };
+        let to = rest.get(..2).and_then(parse_square).ok_or_else(malformed)?;
+
+        let promote_to = match rest[2..] {
+            [] => None,
+            [piece] => Some(promotion_from_letter(piece).ok_or_else(malformed)?),
+            _ => return Err(malformed()),
+        };
+
+        Ok(Move{ moved_piece, from, to, captured_piece, promote_to })
+    }
+}
+
+/// Writes a move as its start and end squares, followed by the promotion piece if any.
+pub fn coordinate(mov: Move) -> String {
+    let mut result = format!("{}{}", mov.from, mov.to).to_lowercase();
+    if let Some(piece) = mov.promote_to {
+        result += &format!("{piece:#}");
+    }
+    result
+}
+
+/// Writes a legal move in short algebraic notation, as read by [`parse_san`].
+/// The start square is only given as far as it is needed to tell the move apart from other legal moves
+/// to the same square by pieces that move alike, or as the file for pawn-like captures,
+/// and `+` or `#` is added when the move gives check or mate.
+pub fn san(pos: &Position, mov: Move) -> String {
+    let virtual_pieces = pos.virtual_pieces(pos.turn);
+    let piece = virtual_pieces.piece_at(mov.from).expect("a piece stands on the start square");
+    let file = |square: Square| ((b'a' + square.file()) as char).to_string();
+    let rank = |square: Square| ((b'1' + square.rank()) as char).to_string();
+
+    let mut result = String::new();
+    if piece != Piece::Pawn {
+        result += &format!("{piece}");
+    }
+
+    let rivals = moves::legal_moves(pos)
+        .into_iter()
+        .filter(|other| other.from != mov.from && other.to == mov.to && virtual_pieces.piece_at(other.from) == Some(piece))
+        .collect::<Vec<_>>();
+    if rivals.is_empty() {
+        if piece == Piece::Pawn && mov.captured_piece.is_some() {
+            result += &file(mov.from);
+        }
+    } else if rivals.iter().all(|other| other.from.file() != mov.from.file()) {
+        result += &file(mov.from);
+    } else if rivals.iter().all(|other| other.from.rank() != mov.from.rank()) {
+        result += &rank(mov.from);
+    } else {
+        result += &file(mov.from);
+        result += &rank(mov.from);
+    }
+
+    if mov.captured_piece.is_some() {
+        result += "x";
+    }
+    result += &file(mov.to);
+    result += &rank(mov.to);
+    if let Some(promote_to) = mov.promote_to {
+        result += &format!("={promote_to}");
+    }
+
+    let after = moves::apply_move(pos, mov);
+    if moves::in_check(&after) {
+        result += if moves::legal_moves(&after).is_empty() { "#" } else { "+" };
+    }
+    result
+}
+
+/// The only legal move that `matches`, with an error naming `text` if there is not exactly one.
+fn select(pos: &Position, text: &str, matches: impl Fn(&Move) -> bool) -> Result<Move, MoveParseError> {
+    let candidates = moves::legal_moves(pos).into_iter().filter(&matches).collect::<Vec<_>>();
+    match candidates.as_slice() {
+        [mov] => Ok(*mov),
+        [] if moves::moves(pos).iter().any(matches) => Err(MoveParseError::LeavesKingInCheck(text.to_string())),
+        [] => Err(MoveParseError::Illegal(text.to_string())),
+        _ => Err(MoveParseError::Ambiguous{ text: text.to_string(), candidates }),
+    }
+}
+
+/// Finds the legal move written in the long notation.
+pub fn parse_long(pos: &Position, s: &str) -> Result<Move, MoveParseError> {
+    let parsed = s.parse::<Move>()?;
+    select(pos, s, |&mov| mov == parsed)
+}
+
+/// Finds the legal move written in coordinate notation, in either case.
+/// A promotion without a piece is ambiguous.
+pub fn parse_coordinate(pos: &Position, s: &str) -> Result<Move, MoveParseError> {
+    let malformed = || MoveParseError::Malformed(s.to_string());
+    let bytes = s.as_bytes();
+    if !(4..=5).contains(&bytes.len()) {
+        return Err(malformed());
+    }
+
+    let from = parse_square(&bytes[..2]).ok_or_else(malformed)?;
+    let to = parse_square(&bytes[2..4]).ok_or_else(malformed)?;
+    let promote_to = match bytes.get(4) {
+        None => None,
+        Some(&piece) => Some(promotion_from_letter(piece).ok_or_else(malformed)?),
+    };
+
+    select(pos, s, |mov| mov.from == from && mov.to == to && (promote_to.is_none() || mov.promote_to == promote_to))
+}
+
+/// Finds the legal move written in short algebraic notation: the letter of the piece the moved piece
+/// moves like (none for a pawn), the file, rank or square it starts from if that is needed
+/// to tell it apart, `x` for a capture, the destination and `=` with the promotion piece.
+/// A trailing `+` or `#` is allowed, but not checked. As in coordinate notation,
+/// a promotion without a piece is ambiguous.
+pub fn parse_san(pos: &Position, s: &str) -> Result<Move, MoveParseError> {
+    let malformed = || MoveParseError::Malformed(s.to_string());
+
+    let body = s.strip_suffix(['+', '#']).unwrap_or(s);
+    let (body, promote_to) = match body.split_once('=').map(|(body, piece)| (body, piece.as_bytes())) {
+        Some((body, &[piece @ (b'N' | b'B' | b'R' | b'Q')])) => (body, promotion_from_letter(piece)),
+        Some(_) => return Err(malformed()),
+        None => (body, None),
+    };
+
+    let (piece, rest) = match body.as_bytes() {
+        [letter @ (b'N' | b'B' | b'R' | b'Q' | b'K'), rest @ ..] => (piece_from_letter(*letter).expect("is a piece letter"), rest),
+        rest => (Piece::Pawn, rest),
+    };
+    let [rest @ .., file @ b'a'..=b'h', rank @ b'1'..=b'8'] = rest else { return Err(malformed()) };
+    let to = Square::new(rank - b'1', file - b'a');
+
+    let (from, capture) = match rest {
+        [from @ .., b'x'] => (from, true),
+        from => (from, false),
+    };
+    let (from_file, from_rank) = match *from {
+        [] => (None, None),
+        [file @ b'a'..=b'h'] => (Some(file - b'a'), None),
+        [rank @ b'1'..=b'8'] => (None, Some(rank - b'1')),
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => (Some(file - b'a'), Some(rank - b'1')),
+        _ => return Err(malformed()),
+    };
+
+    let virtual_pieces = pos.virtual_pieces(pos.turn);
+    select(pos, s, |mov| {
+        virtual_pieces.piece_at(mov.from) == Some(piece)
+            && mov.to == to
+            && from_file.is_none_or(|file| file == mov.from.file())
+            && from_rank.is_none_or(|rank| rank == mov.from.rank())
+            && (!capture || mov.captured_piece.is_some())
+            && (promote_to.is_none() || mov.promote_to == promote_to)
+    })
+}
+
+/// Reads a move in any of the notations, trying the long notation first, then coordinate notation
+/// and then short algebraic notation. The error is the one from the first notation the text fits.
+pub fn parse_move(pos: &Position, s: &str) -> Result<Move, MoveParseError> {
+    let s = s.trim();
+    let mut error = None;
+    for parse in [parse_long, parse_coordinate, parse_san] {
+        match parse(pos, s) {
+            Ok(mov) => return Ok(mov),
+            Err(MoveParseError::Malformed(_)) => (),
+            Err(err) => { error.get_or_insert(err); },
+        }
+    }
+    Err(error.unwrap_or(MoveParseError::Malformed(s.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Strictness;
+
+    #[test]
+    fn readme_moves_in_short_algebraic_notation() {
+        let pos = Position::parse("rnbq1k1r/pp1bpPnp/3P4/p5pQ/8/5P2/1PPPPRB1/RNB1K1N1 b 0 1", Strictness::Strict)
+            .expect("the README position is valid");
+        let legal = moves::legal_moves(&pos);
+        let written = legal.iter().map(|&mov| san(&pos, mov)).collect::<Vec<_>>();
+
+        // the rook moves like a knight, and the pawn on G5 like a queen, as does the bishop on C8.
+        for expected in ["Nb6", "Nc7", "Qxh5", "Qgc5", "Qcc5", "Qxd2+"] {
+            assert!(written.iter().any(|mov| mov == expected), "{expected} missing from {written:?}");
+        }
+
+        for (&mov, text) in legal.iter().zip(&written) {
+            assert_eq!(parse_san(&pos, text), Ok(mov), "{text} reads back as a different move");
+        }
+    }
+}
@@ -1,3 +1,7 @@
+use crate::attacks;
+use crate::moves::Move;
+use crate::zobrist;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Piece {
     Pawn,
@@ -74,11 +78,11 @@ impl Square {
         self.rank
     }
 
-    // pub fn file(&self) -> u8 {
-    //     self.file
-    // }
+    pub fn file(&self) -> u8 {
+        self.file
+    }
 
-    pub fn to_bit_position(&self) -> u8 {
+    pub fn to_bit_position(self) -> u8 {
         let Self{file, rank} = self;
         rank * 8 + file
     }
@@ -88,78 +92,12 @@ impl Square {
     }
 }
 
-impl std::ops::Add<SquareDiff> for Square {
-    type Output = Option<Square>;
-    fn add(self, diff: SquareDiff) -> Self::Output {
-        let rank = self.rank as i8 + diff.rank_diff;
-        let file = self.file as i8 + diff.file_diff;
-
-        if file < 0 || 8 <= file { return None; }
-        if rank < 0 || 8 <= rank { return None; }
-
-        Some(Self{file: file as u8, rank: rank as u8})
-    }
-}
-
 impl std::fmt::Display for Square {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("{}{}", (b'A' + self.file) as char, (b'1' + self.rank) as char))
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct SquareDiff {
-    pub file_diff: i8,
-    pub rank_diff: i8,
-}
-
-impl std::ops::Mul<isize> for SquareDiff {
-    type Output = SquareDiff;
-
-    fn mul(self, rhs: isize) -> Self::Output {
-        Self{file_diff: self.file_diff * rhs as i8, rank_diff: self.rank_diff * rhs as i8}
-    }
-}
-
-impl SquareDiff {
-    pub const fn to_bit_offset_and_mask(&self) -> (i8, u64) {
-
-        const fn bit_range(start: u8, end: u8) -> u64 {
-            let a = if end == 63 { u64::MAX } else { (1 << end) - 1 };
-            let b = if start == 63 { u64::MAX } else { (1 << start) - 1 };
-            return a &! b
-        }
-
-        if self.rank_diff.abs() >= 8 || self.file_diff.abs() >= 8 { return (0, 0); }
-
-        let offset = self.rank_diff * 8 + self.file_diff;
-
-        let row_mask = if self.rank_diff >= 0 {
-            u64::MAX >> (self.rank_diff * 8)
-        } else {
-            u64::MAX << (-self.rank_diff * 8)
-        };
-
-        let file_mask = if self.file_diff >= 0 { 
-            bit_range(0, 8 - self.file_diff as u8) 
-        } else {
-            bit_range((-self.file_diff) as u8, 8) 
-        };
-
-        let file_mask = 0
-            | file_mask << 56 
-            | file_mask << 48 
-            | file_mask << 40 
-            | file_mask << 32 
-            | file_mask << 24 
-            | file_mask << 16
-            | file_mask << 8 
-            | file_mask << 0 ;
-
-        (offset, row_mask & file_mask)
-    }
-}
-
 #[derive(Eq, PartialEq, Hash, Clone, Copy, Default)]
 pub struct BitBoard ( pub u64 );
 impl BitBoard {
@@ -219,16 +157,16 @@ impl PlayerPieces {
         None
     }
 
-    // pub fn pieces(&self, kind: Piece) -> &BitBoard {
-    //     match kind {
-    //         Piece::Pawn => &self.pawns,
-    //         Piece::Knight => &self.knights,
-    //         Piece::Bishop => &self.bishops,
-    //         Piece::Rook => &self.rooks,
-    //         Piece::Queen => &self.queens,
-    //         Piece::King => &self.kings,
-    //     }
-    // }
+    pub fn pieces(&self, kind: Piece) -> &BitBoard {
+        match kind {
+            Piece::Pawn => &self.pawns,
+            Piece::Knight => &self.knights,
+            Piece::Bishop => &self.bishops,
+            Piece::Rook => &self.rooks,
+            Piece::Queen => &self.queens,
+            Piece::King => &self.kings,
+        }
+    }
 
     pub fn mut_pieces(&mut self, kind: Piece) -> &mut BitBoard {
         match kind {
@@ -241,9 +179,12 @@ impl PlayerPieces {
         }
     }
 
+    pub fn boards(&self) -> [BitBoard; 6] {
+        [self.pawns, self.knights, self.bishops, self.rooks, self.queens, self.kings]
+    }
+
     pub fn all(&self) -> BitBoard {
-        BitBoard(0
-            | self.pawns.0
+        BitBoard(self.pawns.0
             | self.knights.0
             | self.bishops.0
             | self.rooks.0
@@ -252,55 +193,407 @@ impl PlayerPieces {
     }
 }
 
-#[derive(Eq, PartialEq, Hash, Default, Clone)]
+/// What [`Position::make_move`] overwrites, so that the move can be taken back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Undo {
+    pub captured_piece: Option<Piece>,
+    pub promote_to: Option<Piece>,
+    pub key: u64,
+    pub halfmove_clock: u16,
+    pub fullmove_number: u16,
+}
+
+#[derive(Clone)]
 pub struct Position {
     pub white: PlayerPieces,
     pub black: PlayerPieces,
     pub turn: Color,
+    /// half-moves since the last capture or pawn move.
+    pub halfmove_clock: u16,
+    /// starts at 1 and is incremented after every black move.
+    pub fullmove_number: u16,
+    /// zobrist key of the placement and side to move, see [`Position::key`].
+    key: u64,
+    /// kept up to date rank by rank, see [`Position::virtual_pieces`].
+    virtual_white: PlayerPieces,
+    virtual_black: PlayerPieces,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self {
+            white: PlayerPieces::default(),
+            black: PlayerPieces::default(),
+            turn: Color::default(),
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            key: 0,
+            virtual_white: PlayerPieces::default(),
+            virtual_black: PlayerPieces::default(),
+        }
+    }
+}
+
+// the move counters are not part of the identity of a position, so that
+// repeated positions compare equal regardless of when they occurred.
+impl PartialEq for Position {
+    fn eq(&self, other: &Self) -> bool {
+        self.white == other.white && self.black == other.black && self.turn == other.turn
+    }
+}
+
+impl Eq for Position {}
+
+impl std::hash::Hash for Position {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(self.key);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionParseError {
+    UnknownPiece { found: char, line: usize, column: usize },
+    RankTooLong { line: usize, column: usize },
+    RankTooShort { line: usize, column: usize },
+    TooFewRanks { found: usize },
+    TrailingGarbage { line: usize, column: usize },
+    InvalidSideToMove { line: usize, column: usize },
+    InvalidCounter { line: usize, column: usize },
+    /// The position fails [`Position::validate`], or, when it is loaded with [`TryFrom`],
+    /// does not have one king on each side.
+    Illegal(Vec<Violation>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strictness {
+    /// Reject positions that fail [`Position::validate`].
+    Strict,
+    /// Accept anything that is syntactically a position.
+    Lenient,
+}
+
+/// A reason why a position can not occur in a game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    MissingKing(Color),
+    TooManyKings(Color, usize),
+    PawnOnPromotionRank(Color, Square),
+    OverlappingPieces(Square),
+    /// The king of the side that just moved can be captured.
+    KingCapturable(Color),
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingKing(color) =>
+                f.write_fmt(format_args!("{color} has no king")),
+            Self::TooManyKings(color, count) =>
+                f.write_fmt(format_args!("{color} has {count} kings")),
+            Self::PawnOnPromotionRank(color, square) =>
+                f.write_fmt(format_args!("{color} has an unpromoted pawn on {square}")),
+            Self::OverlappingPieces(square) =>
+                f.write_fmt(format_args!("more than one piece on {square}")),
+            Self::KingCapturable(color) =>
+                f.write_fmt(format_args!("{color} king can be captured, but it is not {color}'s turn")),
+        }
+    }
 }
 
+impl std::fmt::Display for PositionParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownPiece{found, line, column} =>
+                f.write_fmt(format_args!("{line}:{column}: unknown piece '{found}'")),
+            Self::RankTooLong{line, column} =>
+                f.write_fmt(format_args!("{line}:{column}: rank has more than 8 squares")),
+            Self::RankTooShort{line, column} =>
+                f.write_fmt(format_args!("{line}:{column}: rank has less than 8 squares")),
+            Self::TooFewRanks{found} =>
+                f.write_fmt(format_args!("expected 8 ranks, found {found}")),
+            Self::TrailingGarbage{line, column} =>
+                f.write_fmt(format_args!("{line}:{column}: unexpected input after the position")),
+            Self::InvalidSideToMove{line, column} =>
+                f.write_fmt(format_args!("{line}:{column}: side to move must be 'w' or 'b'")),
+            Self::InvalidCounter{line, column} =>
+                f.write_fmt(format_args!("{line}:{column}: move counter is not a number")),
+            Self::Illegal(violations) => {
+                f.write_str("illegal position: ")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    if i > 0 { f.write_str("; ")?; }
+                    f.write_fmt(format_args!("{violation}"))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::error::Error for PositionParseError {}
+
+fn piece_from_char(c: char) -> Option<(Color, Piece)> {
+    let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+    let piece = match c.to_ascii_lowercase() {
+        'p' => Piece::Pawn,
+        'n' => Piece::Knight,
+        'b' => Piece::Bishop,
+        'r' => Piece::Rook,
+        'q' => Piece::Queen,
+        'k' => Piece::King,
+        _   => return None,
+    };
+    Some((color, piece))
+}
+
+/// Parses an 8-line grid, rank 8 first, with `.` for empty squares.
+/// White is to move.
 impl TryFrom<&str> for Position {
-    type Error = Square;
+    type Error = PositionParseError;
     fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let result = Self::parse_grid(s)?;
+        result.check_kings()?;
+        Ok(result)
+    }
+}
+
+impl Position {
+    fn parse_grid(s: &str) -> Result<Self, PositionParseError> {
         let mut result = Self::default();
-        let mut rank = 7;
-        let mut file = 0;
-        for c in s.chars() {
-            if c == '\n' {
-                if rank == 0 { break }
-                rank -= 1;
-                file  = 0;
-                continue 
+        let mut lines = s.lines().enumerate();
+
+        for rank in (0..8).rev() {
+            let Some((line, rank_str)) = lines.next() else {
+                return Err(PositionParseError::TooFewRanks{ found: 7 - rank as usize });
+            };
+            let line = line + 1;
+
+            let mut file = 0;
+            for (column, c) in rank_str.chars().enumerate() {
+                let column = column + 1;
+                if file == 8 {
+                    return Err(PositionParseError::RankTooLong{ line, column });
+                }
+                if c != '.' {
+                    let (color, piece) = piece_from_char(c)
+                        .ok_or(PositionParseError::UnknownPiece{ found: c, line, column })?;
+                    result.mut_player(color).mut_pieces(piece).set(Square::new(rank, file));
+                }
+                file += 1;
+            }
+
+            if file < 8 {
+                return Err(PositionParseError::RankTooShort{ line, column: file as usize + 1 });
+            }
+        }
+
+        for (line, rest) in lines {
+            if let Some(column) = rest.find(|c: char| !c.is_whitespace()) {
+                return Err(PositionParseError::TrailingGarbage{ line: line + 1, column: column + 1 });
+            }
+        }
+
+        result.refresh();
+        Ok(result)
+    }
+
+    fn check_kings(&self) -> Result<(), PositionParseError> {
+        let violations = self.king_violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PositionParseError::Illegal(violations))
+        }
+    }
+
+    fn king_violations(&self) -> Vec<Violation> {
+        let mut result = vec![];
+        for color in [Color::White, Color::Black] {
+            match self.player(color).kings.size() {
+                0 => result.push(Violation::MissingKing(color)),
+                1 => (),
+                n => result.push(Violation::TooManyKings(color, n)),
             }
-            if file == 8 {
-                return Err(Square{rank, file:file - 1});
+        }
+        result
+    }
+
+    /// Parses a one-line position such as
+    /// `rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w 0 1`.
+    ///
+    /// The fields are piece placement, side to move, halfmove clock and fullmove number.
+    /// Castling and en-passant fields are omitted since neither exists in left-chess.
+    /// The counters may be left out, in which case they default to `0 1`.
+    fn parse_fen(s: &str) -> Result<Self, PositionParseError> {
+        const LINE: usize = 1;
+        let mut result = Self::default();
+
+        // (column, field) pairs, with 1-based columns.
+        let mut fields = s
+            .split(' ')
+            .scan(1, |column, field| {
+                let start = *column;
+                *column += field.chars().count() + 1;
+                Some((start, field))
+            })
+            .filter(|(_, field)| !field.is_empty());
+
+        let (mut column, placement) = fields.next().unwrap_or((1, ""));
+        let mut ranks = placement.split('/');
+
+        for rank in (0..8).rev() {
+            let Some(rank_str) = ranks.next() else {
+                return Err(PositionParseError::TooFewRanks{ found: 7 - rank as usize });
+            };
+
+            let mut file = 0;
+            for c in rank_str.chars() {
+                if file == 8 {
+                    return Err(PositionParseError::RankTooLong{ line: LINE, column });
+                }
+                if let Some(gap) = c.to_digit(10).filter(|gap| (1..=8).contains(gap)) {
+                    if file + gap as u8 > 8 {
+                        return Err(PositionParseError::RankTooLong{ line: LINE, column });
+                    }
+                    file += gap as u8;
+                } else {
+                    let (color, piece) = piece_from_char(c)
+                        .ok_or(PositionParseError::UnknownPiece{ found: c, line: LINE, column })?;
+                    result.mut_player(color).mut_pieces(piece).set(Square::new(rank, file));
+                    file += 1;
+                }
+                column += 1;
             }
-            let square = Square::new(rank, file);
-            match c {
-                'p' => { result.black.pawns.0   |= 1 << square.to_bit_position(); },
-                'n' => { result.black.knights.0 |= 1 << square.to_bit_position(); },
-                'b' => { result.black.bishops.0 |= 1 << square.to_bit_position(); },
-                'r' => { result.black.rooks.0   |= 1 << square.to_bit_position(); },
-                'q' => { result.black.queens.0  |= 1 << square.to_bit_position(); },
-                'k' => { result.black.kings.0   |= 1 << square.to_bit_position(); },
-
-                'P' => { result.white.pawns.0   |= 1 << square.to_bit_position(); },
-                'N' => { result.white.knights.0 |= 1 << square.to_bit_position(); },
-                'B' => { result.white.bishops.0 |= 1 << square.to_bit_position(); },
-                'R' => { result.white.rooks.0   |= 1 << square.to_bit_position(); },
-                'Q' => { result.white.queens.0  |= 1 << square.to_bit_position(); },
-                'K' => { result.white.kings.0   |= 1 << square.to_bit_position(); },
-                '.' => (),
-                _   => { return Err(Square{rank, file}) },
+
+            if file < 8 {
+                return Err(PositionParseError::RankTooShort{ line: LINE, column });
             }
-            file += 1;
+            column += 1; // the '/' separator
+        }
+
+        if ranks.next().is_some() {
+            return Err(PositionParseError::TrailingGarbage{ line: LINE, column: column - 1 });
+        }
+
+        result.turn = match fields.next() {
+            Some((_, "w")) => Color::White,
+            Some((_, "b")) => Color::Black,
+            Some((column, _)) => return Err(PositionParseError::InvalidSideToMove{ line: LINE, column }),
+            None => return Err(PositionParseError::InvalidSideToMove{ line: LINE, column: s.chars().count() + 1 }),
+        };
+
+        if let Some((column, clock)) = fields.next() {
+            result.halfmove_clock = clock.parse()
+                .map_err(|_| PositionParseError::InvalidCounter{ line: LINE, column })?;
+        }
+
+        if let Some((column, number)) = fields.next() {
+            result.fullmove_number = number.parse()
+                .map_err(|_| PositionParseError::InvalidCounter{ line: LINE, column })?;
+        }
+
+        if let Some((column, _)) = fields.next() {
+            return Err(PositionParseError::TrailingGarbage{ line: LINE, column });
         }
+
+        result.refresh();
         Ok(result)
     }
+
+    /// Parses either the one-line notation or the 8-line grid, depending on the shape of the input.
+    ///
+    /// In [`Strictness::Strict`] mode the position must also pass [`Position::validate`],
+    /// while [`Strictness::Lenient`] mode only checks the syntax.
+    pub fn parse(s: &str, strictness: Strictness) -> Result<Self, PositionParseError> {
+        let result = if s.trim().lines().count() == 1 {
+            Self::parse_fen(s.trim())?
+        } else {
+            Self::parse_grid(s)?
+        };
+
+        match strictness {
+            Strictness::Lenient => Ok(result),
+            Strictness::Strict => {
+                let violations = result.validate();
+                if violations.is_empty() {
+                    Ok(result)
+                } else {
+                    Err(PositionParseError::Illegal(violations))
+                }
+            },
+        }
+    }
+
+    /// Lists every way in which the position could not arise in a game.
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut result = self.king_violations();
+
+        for color in [Color::White, Color::Black] {
+            // a pawn can move back to its own first rank by moving like another
+            // piece, but it is always promoted when it reaches the last one.
+            let promotion_rank = match color {
+                Color::White => 7,
+                Color::Black => 0,
+            };
+            for square in self.player(color).pawns {
+                if square.rank() == promotion_rank {
+                    result.push(Violation::PawnOnPromotionRank(color, square));
+                }
+            }
+        }
+
+        let pieces = self.white.boards().into_iter().chain(self.black.boards());
+        let mut seen = BitBoard::default();
+        let mut overlapping = BitBoard::default();
+        for board in pieces {
+            overlapping.0 |= seen.0 & board.0;
+            seen.0 |= board.0;
+        }
+        for square in overlapping {
+            result.push(Violation::OverlappingPieces(square));
+        }
+
+        if self.king_attacked(!self.turn) {
+            result.push(Violation::KingCapturable(!self.turn));
+        }
+
+        result
+    }
+
+    fn write_fen(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for rank in (0..8).rev() {
+            let mut gap = 0;
+            for file in 0..8 {
+                let square = Square::new(rank, file);
+                let piece = match (self.white.piece_at(square), self.black.piece_at(square)) {
+                    (Some(piece), _) => format!("{piece}"),
+                    (_, Some(piece)) => format!("{piece:#}"),
+                    (None, None) => { gap += 1; continue },
+                };
+                if gap > 0 { f.write_fmt(format_args!("{gap}"))?; }
+                gap = 0;
+                f.write_str(&piece)?;
+            }
+            if gap > 0 { f.write_fmt(format_args!("{gap}"))?; }
+            if rank > 0 { f.write_str("/")?; }
+        }
+
+        let turn = match self.turn {
+            Color::White => "w",
+            Color::Black => "b",
+        };
+
+        f.write_fmt(format_args!(" {} {} {}", turn, self.halfmove_clock, self.fullmove_number))
+    }
 }
 
+/// The default format draws the board; the alternate format (`{:#}`) writes the
+/// one-line notation accepted by [`Position::parse`].
 impl std::fmt::Display for Position {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return self.write_fen(f);
+        }
+
         let mut board = [[" ."; 8];8];
         for (bit_board, c) in [
             (self.black.pawns,   " ♙"),
@@ -321,11 +614,15 @@ impl std::fmt::Display for Position {
             }
         }
 
-        f.write_fmt(format_args!("{} to move:\n", self.turn))?;
+        if self.king_attacked(self.turn) {
+            f.write_fmt(format_args!("{} to move, in check:\n", self.turn))?;
+        } else {
+            f.write_fmt(format_args!("{} to move:\n", self.turn))?;
+        }
         for (i, line) in board.iter().enumerate().rev() {
             f.write_fmt(format_args!("{} ", (b'1' + i as u8) as char))?;
             for square in line {
-                f.write_str(*square)?;
+                f.write_str(square)?;
             }
             f.write_fmt(format_args!("{}", '\n'))?;
         }
@@ -375,6 +672,93 @@ impl FromIterator<Square> for BitBoard {
 }
 
 impl Position {
+    /// A 64-bit zobrist key of the piece placement and side to move.
+    /// Equal positions always have equal keys.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Updates the key and the virtual pieces after the placement or turn were modified directly.
+    pub fn refresh(&mut self) {
+        self.key = zobrist::hash(self);
+        for rank in 0..8 {
+            self.update_virtual_rank(rank);
+        }
+    }
+
+    // adds or removes a piece, without updating the virtual pieces.
+    fn toggle_piece(&mut self, color: Color, piece: Piece, square: Square) {
+        self.mut_player(color).mut_pieces(piece).0 ^= 1 << square.to_bit_position();
+        self.key ^= zobrist::piece_key(color, piece, square);
+    }
+
+
+    pub fn pass_turn(&mut self) {
+        self.turn = !self.turn;
+        self.key ^= zobrist::turn_key();
+    }
+
+    /// Plays a move in place, returning what is needed to take it back with [`Position::unmake_move`].
+    pub fn make_move(&mut self, mov: Move) -> Undo {
+        let undo = Undo{
+            captured_piece: mov.captured_piece,
+            promote_to: mov.promote_to,
+            key: self.key,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+        };
+        let mover = self.turn;
+
+        // the counters come from the input and may already be at their maximum.
+        if mov.moved_piece == Piece::Pawn || mov.captured_piece.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock = self.halfmove_clock.saturating_add(1);
+        }
+
+        if mover == Color::Black {
+            self.fullmove_number = self.fullmove_number.saturating_add(1);
+        }
+
+        self.toggle_piece(mover, mov.moved_piece, mov.from);
+
+        if let Some(piece) = mov.captured_piece {
+            self.toggle_piece(!mover, piece, mov.to);
+        }
+
+        self.toggle_piece(mover, mov.promote_to.unwrap_or(mov.moved_piece), mov.to);
+        self.pass_turn();
+
+        // only the ranks the move left and entered can see a different piece to the left.
+        self.update_virtual_rank(mov.from.rank());
+        if mov.to.rank() != mov.from.rank() {
+            self.update_virtual_rank(mov.to.rank());
+        }
+
+        undo
+    }
+
+    /// Takes back `mov`, which must be the last move made on this position.
+    pub fn unmake_move(&mut self, mov: Move, undo: Undo) {
+        let mover = !self.turn;
+
+        self.mut_player(mover).mut_pieces(undo.promote_to.unwrap_or(mov.moved_piece)).unset(mov.to);
+        if let Some(piece) = undo.captured_piece {
+            self.mut_player(!mover).mut_pieces(piece).set(mov.to);
+        }
+        self.mut_player(mover).mut_pieces(mov.moved_piece).set(mov.from);
+
+        self.turn = mover;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.fullmove_number = undo.fullmove_number;
+        self.key = undo.key;
+
+        self.update_virtual_rank(mov.from.rank());
+        if mov.to.rank() != mov.from.rank() {
+            self.update_virtual_rank(mov.to.rank());
+        }
+    }
+
     pub fn all(&self) -> BitBoard {
         BitBoard(self.black.all().0 | self.white.all().0)
     }
@@ -402,57 +786,103 @@ impl Position {
         }
     }
 
-    pub fn to_virtual_position(&self) -> Position {
-        fn rot_right(s: i8, b: u64) -> u64 {
-            let (shift_l, mask_l) = SquareDiff{rank_diff: 0, file_diff: (s + 8) % 8}.to_bit_offset_and_mask();
-            let (shift_r, mask_r) = SquareDiff{rank_diff: 0, file_diff: (s + 8) % 8 - 8}.to_bit_offset_and_mask();
+    /// The pieces of `color`, each labelled by the piece it moves like:
+    /// the first piece found moving left from it, from `color`'s perspective,
+    /// wrapping around the end of the rank.
+    pub fn virtual_pieces(&self, color: Color) -> &PlayerPieces {
+        match color {
+            Color::Black => &self.virtual_black,
+            Color::White => &self.virtual_white,
+        }
+    }
 
-            let result = (b & mask_l).overflowing_shl(shift_l.abs() as u32).0 | (b & mask_r).overflowing_shr(shift_r.abs() as u32).0;
+    /// The squares `color` could capture on, counting the ones its own pieces stand on.
+    /// Each piece attacks as the piece it moves like, so pawns attack diagonally forward
+    /// and a piece's attacks change when the pieces on its rank do.
+    pub fn attacks(&self, color: Color) -> BitBoard {
+        let pieces = self.virtual_pieces(color);
+        let occupied = self.all();
+        let mut result = 0;
+        for square in pieces.pawns   { result |= attacks::pawn_attacks(color, square).0; }
+        for square in pieces.knights { result |= attacks::knight_attacks(square).0; }
+        for square in pieces.bishops { result |= attacks::bishop_attacks(square, occupied).0; }
+        for square in pieces.rooks   { result |= attacks::rook_attacks(square, occupied).0; }
+        for square in pieces.queens  { result |= attacks::queen_attacks(square, occupied).0; }
+        for square in pieces.kings   { result |= attacks::king_attacks(square).0; }
+        BitBoard(result)
+    }
 
-            result
-        }        
+    /// Whether a piece of `by` could capture on `square`, see [`Position::attacks`].
+    /// Looks from `square` outwards, which is cheaper than computing the whole attack map.
+    pub fn is_attacked(&self, square: Square, by: Color) -> bool {
+        let pieces = self.virtual_pieces(by);
+        let occupied = self.all();
+        let diagonal = pieces.bishops.0 | pieces.queens.0;
+        let straight = pieces.rooks.0 | pieces.queens.0;
+        attacks::pawn_attacks(!by, square).0 & pieces.pawns.0 != 0
+            || attacks::knight_attacks(square).0 & pieces.knights.0 != 0
+            || attacks::king_attacks(square).0 & pieces.kings.0 != 0
+            || attacks::bishop_attacks(square, occupied).0 & diagonal != 0
+            || attacks::rook_attacks(square, occupied).0 & straight != 0
+    }
 
-        let BitBoard(white_pieces) = self.white.all();
-        let BitBoard(black_pieces) = self.black.all();
-        let all_pieces = white_pieces | black_pieces;
+    /// Whether a king of `color` could be captured by the opponent.
+    pub fn king_attacked(&self, color: Color) -> bool {
+        self.player(color).kings.into_iter().any(|square| self.is_attacked(square, !color))
+    }
 
-        let mut result = Position::default();
+    /// A position with the same pieces, each replaced by the piece it moves like.
+    pub fn to_virtual_position(&self) -> Position {
+        let mut result = Position{
+            white: self.virtual_white.clone(),
+            black: self.virtual_black.clone(),
+            ..Position::default()
+        };
+        result.turn = self.turn;
+        result
+    }
 
-        for (mut pieces, board) in [
-            (self.white.pawns.0   | self.black.pawns.0,   &mut result.white.pawns.0),
-            (self.white.knights.0 | self.black.knights.0, &mut result.white.knights.0),
-            (self.white.bishops.0 | self.black.bishops.0, &mut result.white.bishops.0),
-            (self.white.rooks.0   | self.black.rooks.0,   &mut result.white.rooks.0),
-            (self.white.queens.0  | self.black.queens.0,  &mut result.white.queens.0),
-            (self.white.kings.0   | self.black.kings.0,   &mut result.white.kings.0),
-        ] {
-            for _ in 0..8 {
-                pieces = rot_right(1, pieces);
-                *board |= pieces & white_pieces;
-                pieces &= !all_pieces;
+    /// Recomputes the virtual pieces on one rank, which only depend on the pieces on that rank.
+    fn update_virtual_rank(&mut self, rank: u8) {
+        let rank_mask = 0xff << (rank * 8);
+        for board in [&mut self.virtual_white, &mut self.virtual_black] {
+            for kind in [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King] {
+                board.mut_pieces(kind).0 &= !rank_mask;
             }
         }
 
-        for (mut pieces, board) in [
-            (self.white.pawns.0   | self.black.pawns.0,   &mut result.black.pawns.0),
-            (self.white.knights.0 | self.black.knights.0, &mut result.black.knights.0),
-            (self.white.bishops.0 | self.black.bishops.0, &mut result.black.bishops.0),
-            (self.white.rooks.0   | self.black.rooks.0,   &mut result.black.rooks.0),
-            (self.white.queens.0  | self.black.queens.0,  &mut result.black.queens.0),
-            (self.white.kings.0   | self.black.kings.0,   &mut result.black.kings.0),
-        ] {
-            for _ in 0..8 {
-                pieces = rot_right(-1, pieces);
-                *board |= pieces & black_pieces;
-                pieces &= !all_pieces;
+        let occupied = (self.all().0 >> (rank * 8)) as u8;
+        let white = (self.white.all().0 >> (rank * 8)) as u8;
+
+        for file in 0..8 {
+            if occupied & (1 << file) == 0 { continue }
+
+            let (color, step) = if white & (1 << file) != 0 {
+                (Color::White, 7) // one file towards A, modulo 8
+            } else {
+                (Color::Black, 1) // one file towards H
+            };
+
+            // terminates at the piece itself if it is alone on the rank.
+            let mut neighbour = (file + step) % 8;
+            while occupied & (1 << neighbour) == 0 {
+                neighbour = (neighbour + step) % 8;
             }
-        }
 
-        result
+            let neighbour = Square::new(rank, neighbour);
+            let kind = self.white.piece_at(neighbour)
+                .or(self.black.piece_at(neighbour))
+                .expect("square is occupied");
+
+            let board = match color {
+                Color::White => &mut self.virtual_white,
+                Color::Black => &mut self.virtual_black,
+            };
+            board.mut_pieces(kind).set(Square::new(rank, file));
+        }
     }
 }
 
-
 impl FromIterator<(Square, Color, Piece)> for Position {
     fn from_iter<T: IntoIterator<Item = (Square, Color, Piece)>>(iter: T) -> Self {
         let mut result = Self::default();
@@ -473,6 +903,127 @@ impl FromIterator<(Square, Color, Piece)> for Position {
             };
             bit_board.0 |= 1 << square.to_bit_position();
         }
+        result.refresh();
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moves;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    #[test]
+    fn make_and_unmake_keep_the_position_consistent() {
+        let start = Position::parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w 0 1", Strictness::Strict)
+            .expect("the start position is valid");
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        for _ in 0..50 {
+            let mut pos = start.clone();
+            let mut played = vec![];
+            while played.len() < 100 {
+                let Some(&mov) = moves::legal_moves(&pos).choose(&mut rng) else { break };
+                let before = pos.clone();
+                let undo = pos.make_move(mov);
+                played.push((mov, undo, before));
+
+                assert_eq!(pos.key(), zobrist::hash(&pos), "key after {mov}");
+                let mut refreshed = pos.clone();
+                refreshed.refresh();
+                for color in [Color::White, Color::Black] {
+                    assert!(pos.virtual_pieces(color) == refreshed.virtual_pieces(color), "virtual pieces after {mov}");
+                }
+            }
+
+            while let Some((mov, undo, before)) = played.pop() {
+                pos.unmake_move(mov, undo);
+                assert!(pos == before, "placement after taking back {mov}");
+                assert_eq!(pos.key(), before.key(), "key after taking back {mov}");
+                for color in [Color::White, Color::Black] {
+                    assert!(pos.virtual_pieces(color) == before.virtual_pieces(color), "virtual pieces after taking back {mov}");
+                }
+                assert_eq!(
+                    (pos.halfmove_clock, pos.fullmove_number),
+                    (before.halfmove_clock, before.fullmove_number),
+                    "counters after taking back {mov}",
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn move_counters_stop_at_their_maximum() {
+        let mut pos = Position::parse("4k3/8/8/8/8/8/8/3RK3 b 65535 65535", Strictness::Strict)
+            .expect("the position is valid");
+        let before = pos.clone();
+        let mov = moves::legal_moves(&pos).into_iter().find(|mov| mov.captured_piece.is_none())
+            .expect("black has a quiet move");
+
+        let undo = pos.make_move(mov);
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (u16::MAX, u16::MAX));
+        pos.unmake_move(mov, undo);
+        assert_eq!((pos.halfmove_clock, pos.fullmove_number), (before.halfmove_clock, before.fullmove_number));
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_offending_character() {
+        let grid = "rnbqkbnr\npppppppp\n..x.....\n........\n........\n........\nPPPPPPPP\nRNBQKBNR\n";
+        assert!(matches!(
+            Position::parse(grid, Strictness::Lenient),
+            Err(PositionParseError::UnknownPiece{ found: 'x', line: 3, column: 3 }),
+        ));
+
+        let fen = "rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w 0 1";
+        assert!(matches!(
+            Position::parse(fen, Strictness::Lenient),
+            Err(PositionParseError::RankTooLong{ line: 1, column: 18 }),
+        ));
+
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w x 1";
+        assert!(matches!(
+            Position::parse(fen, Strictness::Lenient),
+            Err(PositionParseError::InvalidCounter{ line: 1, column: 47 }),
+        ));
+    }
+
+    #[test]
+    fn only_strict_loading_rejects_illegal_positions() {
+        let fen = "P3k3/8/8/8/8/8/8/4K3 w 0 1";
+        assert_eq!(
+            Position::parse(fen, Strictness::Strict).err(),
+            Some(PositionParseError::Illegal(vec![Violation::PawnOnPromotionRank(Color::White, Square::new(7, 0))])),
+        );
+        assert!(Position::parse(fen, Strictness::Lenient).is_ok());
+    }
+
+    #[test]
+    fn is_attacked_agrees_with_the_attack_maps() {
+        let load = |fen| Position::parse(fen, Strictness::Lenient).expect("the position is valid");
+        let start = load("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w 0 1");
+        let readme = load("rnbq1k1r/pp1bpPnp/3P4/p5pQ/8/5P2/1PPPPRB1/RNB1K1N1 b 0 1");
+
+        // the king on E1 moves like the rook on A1, so it attacks up the E file.
+        let sliding_king = load("7k/8/8/8/8/8/8/R3K3 w 0 1");
+        assert!(sliding_king.virtual_pieces(Color::White).rooks.has(Square::new(0, 4)));
+        assert!(sliding_king.attacks(Color::White).has(Square::new(7, 4)));
+
+        // the knight on A1 finds the pawn on H1 by wrapping around the rank, so it attacks like a pawn.
+        let wrapping_pawn = load("7k/8/8/8/8/8/8/N3K2P w 0 1");
+        assert!(wrapping_pawn.virtual_pieces(Color::White).pawns.has(Square::new(0, 0)));
+        assert!(wrapping_pawn.attacks(Color::White).has(Square::new(1, 1)));
+        assert!(!wrapping_pawn.attacks(Color::White).has(Square::new(2, 1)));
+
+        for pos in [start, readme, sliding_king, wrapping_pawn] {
+            for color in [Color::White, Color::Black] {
+                let attacks = pos.attacks(color);
+                for index in 0..64 {
+                    let square = Square::from_bit_position(index);
+                    assert_eq!(pos.is_attacked(square, color), attacks.has(square), "{color} on {square} in {pos:#}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,470 @@
+//! Game records, in a format modelled after PGN: tag pairs such as `[White "lh-chess"]`,
+//! then the moves in short algebraic notation (see [`crate::notation`]) with move numbers,
+//! comments in braces and the result.
+//!
+//! The `Variant` tag is always `leftchess`, and the `Rules` tag lists how the engine's rules
+//! differ from plain left-chess. Games that do not begin from the usual start position
+//! have `SetUp` and `FEN` tags, the latter in the one-line notation of [`Position::parse`].
+
+use crate::moves::Move;
+use crate::notation::{self, MoveParseError};
+use crate::position::{Color, Position, PositionParseError, Strictness};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const VARIANT: &str = "leftchess";
+pub const RULES: &str = "no castling, no double pawn step, no en passant";
+
+/// The tags every record starts with, in this order, followed by `Result`.
+const ROSTER: [&str; 6] = ["Event", "Site", "Date", "Round", "White", "Black"];
+
+/// Movetext lines are broken before they get longer than this.
+const LINE_WIDTH: usize = 80;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// the game is still going, or was abandoned.
+    Ongoing,
+}
+
+impl GameResult {
+    fn from_token(s: &str) -> Option<Self> {
+        match s {
+            "1-0"     => Some(Self::WhiteWins),
+            "0-1"     => Some(Self::BlackWins),
+            "1/2-1/2" => Some(Self::Draw),
+            "*"       => Some(Self::Ongoing),
+            _         => None,
+        }
+    }
+}
+
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::WhiteWins => "1-0",
+            Self::BlackWins => "0-1",
+            Self::Draw      => "1/2-1/2",
+            Self::Ongoing   => "*",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedMove {
+    pub mov: Move,
+    /// the comment that follows the move, without the braces.
+    pub comment: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct GameRecord {
+    /// the tag pairs other than `Result`, `Variant`, `Rules`, `SetUp` and `FEN`,
+    /// which are written from the other fields.
+    pub tags: Vec<(String, String)>,
+    pub start: Position,
+    /// the comment before the first move.
+    pub comment: Option<String>,
+    pub moves: Vec<RecordedMove>,
+    pub result: GameResult,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordParseError {
+    InvalidTag { line: usize },
+    UnsupportedVariant(String),
+    InvalidPosition(PositionParseError),
+    InvalidMove { line: usize, error: MoveParseError },
+    UnterminatedComment { line: usize },
+    /// Something that is not a move, comment, move number or result, or anything after the result.
+    UnexpectedToken { line: usize, token: String },
+}
+
+impl std::fmt::Display for RecordParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidTag{line} =>
+                f.write_fmt(format_args!("{line}: tags must look like [Name \"value\"]")),
+            Self::UnsupportedVariant(variant) =>
+                f.write_fmt(format_args!("unsupported variant '{variant}', only {VARIANT} is played")),
+            Self::InvalidPosition(err) =>
+                f.write_fmt(format_args!("invalid FEN tag: {err}")),
+            Self::InvalidMove{line, error} =>
+                f.write_fmt(format_args!("{line}: {error}")),
+            Self::UnterminatedComment{line} =>
+                f.write_fmt(format_args!("{line}: comment is not closed")),
+            Self::UnexpectedToken{line, token} =>
+                f.write_fmt(format_args!("{line}: unexpected '{token}'")),
+        }
+    }
+}
+
+impl std::error::Error for RecordParseError {}
+
+/// Today's date in the `YYYY.MM.DD` form of the `Date` tag, in UTC.
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() / 86400) as i64;
+
+    // counts from 0000-03-01, so that the leap day ends the year,
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719468;
+    let (era, day_of_era) = (days.div_euclid(146097), days.rem_euclid(146097));
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+fn is_standard_start(pos: &Position) -> bool {
+    format!("{pos:#}") == format!("{:#}", crate::start_position())
+}
+
+/// Reads a `[Name "value"]` line, with `\"` and `\\` escaped in the value.
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.push(chars.next()?),
+            '"' => return None,
+            c => result.push(c),
+        }
+    }
+    Some((name.to_string(), result))
+}
+
+impl GameRecord {
+    /// A record with no moves and unknown tags, as a game from `start` begins.
+    pub fn new(start: &Position) -> Self {
+        GameRecord{
+            tags: ROSTER.iter().map(|&name| (name.to_string(), String::new())).collect(),
+            start: start.clone(),
+            comment: None,
+            moves: vec![],
+            result: GameResult::Ongoing,
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Replaces the value of the tag, or adds it after the others.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// The position before every move, followed by the one after the last move,
+    /// like the history kept by a game in progress.
+    pub fn positions(&self) -> Vec<Position> {
+        let mut result = vec![self.start.clone()];
+        for recorded in &self.moves {
+            let mut pos = result.last().expect("starts with a position").clone();
+            pos.make_move(recorded.mov);
+            result.push(pos);
+        }
+        result
+    }
+
+    fn write_tag(f: &mut std::fmt::Formatter<'_>, name: &str, value: &str) -> std::fmt::Result {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        f.write_fmt(format_args!("[{name} \"{value}\"]\n"))
+    }
+
+    /// The movetext as separate words, which are then broken into lines.
+    fn movetext(&self) -> Vec<String> {
+        let mut result = vec![];
+        let mut pos = self.start.clone();
+        // a black move needs its number if something comes between it and the white move.
+        let mut numbered = false;
+
+        if let Some(comment) = &self.comment {
+            result.push(format!("{{{comment}}}"));
+        }
+        for RecordedMove{mov, comment} in &self.moves {
+            match pos.turn {
+                Color::White => result.push(format!("{}.", pos.fullmove_number)),
+                Color::Black if !numbered => result.push(format!("{}...", pos.fullmove_number)),
+                Color::Black => (),
+            }
+            result.push(notation::san(&pos, *mov));
+            numbered = pos.turn == Color::White && comment.is_none();
+            if let Some(comment) = comment {
+                result.push(format!("{{{comment}}}"));
+            }
+            pos.make_move(*mov);
+        }
+        result.push(self.result.to_string());
+        result
+    }
+}
+
+impl std::fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for name in ROSTER {
+            let value = match self.tag(name) {
+                Some(value) if !value.is_empty() => value,
+                _ if name == "Date" => "????.??.??",
+                _ => "?",
+            };
+            Self::write_tag(f, name, value)?;
+        }
+        Self::write_tag(f, "Result", &self.result.to_string())?;
+        Self::write_tag(f, "Variant", VARIANT)?;
+        Self::write_tag(f, "Rules", RULES)?;
+        if !is_standard_start(&self.start) {
+            Self::write_tag(f, "SetUp", "1")?;
+            Self::write_tag(f, "FEN", &format!("{:#}", self.start))?;
+        }
+        for (name, value) in &self.tags {
+            if !ROSTER.contains(&name.as_str()) {
+                Self::write_tag(f, name, value)?;
+            }
+        }
+        f.write_str("\n")?;
+
+        let mut line_length = 0;
+        for word in self.movetext() {
+            if line_length > 0 && line_length + 1 + word.len() > LINE_WIDTH {
+                f.write_str("\n")?;
+                line_length = 0;
+            }
+            if line_length > 0 {
+                f.write_str(" ")?;
+                line_length += 1;
+            }
+            f.write_str(&word)?;
+            line_length += word.len();
+        }
+        f.write_str("\n")
+    }
+}
+
+/// Reads a record, checking every move against the position it is played in.
+/// Moves may be in any notation accepted by [`notation::parse_move`];
+/// variations and annotation glyphs are not supported.
+impl std::str::FromStr for GameRecord {
+    type Err = RecordParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate().map(|(i, line)| (i + 1, line)).peekable();
+
+        let mut tags = vec![];
+        let mut start = crate::start_position();
+        let mut tag_result = None;
+        while let Some(&(line, text)) = lines.peek() {
+            let text = text.trim();
+            if !text.is_empty() && !text.starts_with('[') { break }
+            lines.next();
+            if text.is_empty() { continue }
+
+            let (name, value) = parse_tag(text).ok_or(RecordParseError::InvalidTag{ line })?;
+            match name.as_str() {
+                "Variant" if value.eq_ignore_ascii_case(VARIANT) => (),
+                "Variant" => return Err(RecordParseError::UnsupportedVariant(value)),
+                "FEN" => start = Position::parse(&value, Strictness::Strict).map_err(RecordParseError::InvalidPosition)?,
+                "Result" => tag_result = GameResult::from_token(&value),
+                "Rules" | "SetUp" => (),
+                _ => tags.push((name, value)),
+            }
+        }
+
+        let mut record = GameRecord{ tags, start: start.clone(), comment: None, moves: vec![], result: GameResult::Ongoing };
+        let mut pos = start;
+        let mut token_result = None;
+        let mut comment: Option<(usize, String)> = None;
+
+        for (line, text) in lines {
+            let mut rest = text;
+            while !rest.is_empty() {
+                // inside a comment that began on an earlier line.
+                if let Some((_, text)) = &mut comment {
+                    let (inside, after) = rest.split_once('}').unwrap_or((rest, ""));
+                    text.push(' ');
+                    text.push_str(inside.trim());
+                    if rest.contains('}') {
+                        let text = text.trim().to_string();
+                        match record.moves.last_mut() {
+                            Some(last) => last.comment = Some(text),
+                            None => record.comment = Some(text),
+                        }
+                        comment = None;
+                    }
+                    rest = after;
+                    continue;
+                }
+
+                rest = rest.trim_start();
+                if rest.is_empty() || rest.starts_with(';') { break }
+                if let Some(after) = rest.strip_prefix('{') {
+                    comment = Some((line, String::new()));
+                    rest = after;
+                    continue;
+                }
+
+                let end = rest.find(|c: char| c.is_whitespace() || c == '{' || c == ';').unwrap_or(rest.len());
+                let (token, after) = rest.split_at(end);
+                rest = after;
+
+                let unexpected = || RecordParseError::UnexpectedToken{ line, token: token.to_string() };
+                if token_result.is_some() {
+                    return Err(unexpected());
+                }
+                if let Some(result) = GameResult::from_token(token) {
+                    token_result = Some(result);
+                    continue;
+                }
+
+                // move numbers, which may be joined to the move, as in `1.g3`.
+                let digits = token.trim_start_matches(|c: char| c.is_ascii_digit());
+                let token = if digits.len() < token.len() {
+                    if !digits.starts_with('.') { return Err(unexpected()) }
+                    digits.trim_start_matches('.')
+                } else {
+                    token
+                };
+                if token.is_empty() { continue }
+
+                let mov = notation::parse_move(&pos, token)
+                    .map_err(|error| RecordParseError::InvalidMove{ line, error })?;
+                pos.make_move(mov);
+                record.moves.push(RecordedMove{ mov, comment: None });
+            }
+        }
+
+        if let Some((line, _)) = comment {
+            return Err(RecordParseError::UnterminatedComment{ line });
+        }
+        record.result = token_result.or(tag_result).unwrap_or(GameResult::Ongoing);
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A record of the moves, given in short algebraic notation, played from `start`.
+    fn record(start: &Position, moves: &[&str]) -> GameRecord {
+        let mut record = GameRecord::new(start);
+        let mut pos = start.clone();
+        for text in moves {
+            let mov = notation::parse_san(&pos, text).expect("the move is legal");
+            pos.make_move(mov);
+            record.moves.push(RecordedMove{ mov, comment: None });
+        }
+        record
+    }
+
+    /// A record of the first legal move in each position, for at most `plies` moves.
+    fn first_moves(start: &Position, plies: usize) -> GameRecord {
+        let mut record = GameRecord::new(start);
+        let mut pos = start.clone();
+        while let Some(&mov) = crate::moves::legal_moves(&pos).first().filter(|_| record.moves.len() < plies) {
+            pos.make_move(mov);
+            record.moves.push(RecordedMove{ mov, comment: None });
+        }
+        record
+    }
+
+    fn movetext(record: &GameRecord) -> String {
+        let text = record.to_string();
+        let (_, movetext) = text.split_once("\n\n").expect("a blank line follows the tags");
+        movetext.to_string()
+    }
+
+    #[test]
+    fn black_moves_are_numbered_after_a_comment() {
+        let mut game = record(&crate::start_position(), &["g3", "b6", "Nb3", "Qb7"]);
+        game.moves[0].comment = Some("the bishop moves like a knight".to_string());
+        assert_eq!(movetext(&game), "1. g3 {the bishop moves like a knight} 1... b6 2. Nb3 Qb7 *\n");
+
+        let mut start = crate::start_position();
+        start.make_move(game.moves[0].mov);
+        assert_eq!(movetext(&record(&start, &["b6", "Nb3"])), "1... b6 2. Nb3 *\n");
+    }
+
+    #[test]
+    fn long_movetext_is_broken_into_lines() {
+        let mut game = first_moves(&crate::start_position(), 60);
+        game.moves[7].comment = Some("a comment that is too long to fit on the rest of the line".to_string());
+        let text = movetext(&game);
+
+        assert!(text.lines().count() > 1, "{text}");
+        assert!(text.lines().all(|line| line.len() <= LINE_WIDTH), "{text}");
+        let words = game.movetext().join(" ");
+        assert_eq!(text.split_whitespace().collect::<Vec<_>>(), words.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn other_starts_are_written_as_tags() {
+        assert!(!GameRecord::new(&crate::start_position()).to_string().contains("[SetUp"));
+
+        let fen = "4k3/8/8/8/8/8/8/3RK3 b 3 40";
+        let start = Position::parse(fen, Strictness::Strict).expect("the position is valid");
+        let text = GameRecord::new(&start).to_string();
+        assert!(text.contains("[SetUp \"1\"]\n"), "{text}");
+        assert!(text.contains(&format!("[FEN \"{fen}\"]\n")), "{text}");
+    }
+
+    #[test]
+    fn quotes_and_backslashes_are_escaped_in_tags() {
+        let mut game = GameRecord::new(&crate::start_position());
+        game.set_tag("Event", r#"the "final" \ round"#);
+        let text = game.to_string();
+        assert!(text.contains(r#"[Event "the \"final\" \\ round"]"#), "{text}");
+
+        let read = text.parse::<GameRecord>().expect("the record can be read");
+        assert_eq!(read.tag("Event"), Some(r#"the "final" \ round"#));
+    }
+
+    #[test]
+    fn written_records_read_back_the_same() {
+        let start = Position::parse("rnbq1k1r/pp1bpPnp/3P4/p5pQ/8/5P2/1PPPPRB1/RNB1K1N1 b 0 1", Strictness::Strict)
+            .expect("the README position is valid");
+        let mut game = first_moves(&start, 30);
+        for (name, value) in [("Event", "test"), ("Site", "here"), ("Date", "2024.01.31"), ("Round", "1"),
+            ("White", "lh-chess"), ("Black", "Human"), ("Annotator", "someone")] {
+            game.set_tag(name, value);
+        }
+        game.comment = Some("from the README".to_string());
+        game.moves[0].comment = Some("a comment after a black move".to_string());
+        game.result = GameResult::WhiteWins;
+
+        let read = game.to_string().parse::<GameRecord>().expect("the record can be read");
+        assert_eq!(read.tags, game.tags);
+        assert!(read.start == game.start);
+        assert_eq!(read.comment, game.comment);
+        assert_eq!(read.moves, game.moves);
+        assert_eq!(read.result, game.result);
+        assert_eq!(read.to_string(), game.to_string());
+    }
+
+    #[test]
+    fn records_are_read_with_comments_and_move_numbers() {
+        let text = "[Event \"test\"]\n[Variant \"leftchess\"]\n\n\
+            {the bishop moves like a knight} 1. g3 b6 {a comment\nover two lines} 2.Nb3 Qb7 ; to the end of the line\n\
+            3. Nc3 *\n";
+        let game = text.parse::<GameRecord>().expect("the record can be read");
+
+        assert_eq!(game.comment.as_deref(), Some("the bishop moves like a knight"));
+        assert_eq!(game.moves[1].comment.as_deref(), Some("a comment over two lines"));
+        assert_eq!(game.result, GameResult::Ongoing);
+        let positions = game.positions().iter().map(|pos| format!("{pos:#}")).collect::<Vec<_>>();
+        assert_eq!(positions.len(), 6);
+        assert_eq!(positions[3], "rnbqkbnr/p1pppppp/1p6/8/8/1B4P1/PPPPPP1P/RN1QKBNR b 1 2");
+    }
+}
@@ -0,0 +1,57 @@
+//! Searching on a thread of its own, so that the GUI protocols keep answering commands meanwhile.
+
+use crate::moves::{self, Engine, Move};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// An engine that is either idle or lent to the thread running its current search.
+pub struct SearchThread {
+    /// the engine while no search is running.
+    engine: Option<Engine>,
+    /// the thread running the current search, which hands the engine back when it ends.
+    search: Option<JoinHandle<Engine>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl SearchThread {
+    pub fn new(engine: Engine) -> Self {
+        SearchThread{ stop: engine.stop_signal(), engine: Some(engine), search: None }
+    }
+
+    /// The engine, after stopping the search if one is running.
+    pub fn engine(&mut self) -> &mut Engine {
+        if self.search.is_some() {
+            self.stop();
+        }
+        self.wait()
+    }
+
+    /// The engine, after letting the search run to its end if one is running.
+    pub fn wait(&mut self) -> &mut Engine {
+        if let Some(search) = self.search.take() {
+            self.engine = Some(search.join().expect("the search does not panic"));
+        }
+        self.engine.as_mut().expect("the engine is idle after the search ends")
+    }
+
+    /// Asks the running search, if any, to end with the best move found so far, without waiting for it.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Searches the engine's current position with its current limits, then calls `done` on the
+    /// search thread with the best move, which is only missing when there are no legal moves.
+    pub fn start(&mut self, done: impl FnOnce(&mut Engine, Option<Move>) + Send + 'static) {
+        self.engine();
+        self.stop.store(false, Ordering::Relaxed);
+        let mut engine = self.engine.take().expect("the engine is idle");
+        self.search = Some(std::thread::spawn(move || {
+            // a search stopped before its first iteration has no result, but a move is still owed.
+            let best = engine.suggest_move().map(|(mov, _)| mov)
+                .or_else(|| moves::legal_moves(engine.current_position()).first().copied());
+            done(&mut engine, best);
+            engine
+        }));
+    }
+}
@@ -0,0 +1,59 @@
+use crate::moves::Move;
+
+/// How the stored score relates to the true score of the position.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bound {
+    /// the score is exact.
+    Exact,
+    /// the search failed high: the true score is at least this.
+    Lower,
+    /// the search failed low: the true score is at most this.
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    pub key: u64,
+    /// remaining search depth below the position when it was stored.
+    pub depth: isize,
+    pub score: isize,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+/// A fixed-size hash table of search results, indexed by zobrist key.
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl TranspositionTable {
+    pub const DEFAULT_SIZE_MB: usize = 16;
+
+    pub fn with_size_mb(megabytes: usize) -> Self {
+        let capacity = (megabytes * 1024 * 1024 / std::mem::size_of::<Option<Entry>>()).max(1);
+        Self{ entries: vec![None; capacity] }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn probe(&self, key: u64) -> Option<&Entry> {
+        self.entries[self.index(key)]
+            .as_ref()
+            .filter(|entry| entry.key == key)
+    }
+
+    /// Stores an entry, unless its slot holds a deeper search of the same position.
+    pub fn store(&mut self, entry: Entry) {
+        let index = self.index(entry.key);
+        match &self.entries[index] {
+            Some(old) if old.key == entry.key && old.depth > entry.depth => (),
+            _ => self.entries[index] = Some(entry),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Fits the evaluation weights to the outcomes of self-play games, by Texel's method:
+//! the evaluation of every position, squashed by a sigmoid, should predict the result of its game.
+
+use crate::evaluation::{self, EvalParams, PAWN_SCORE};
+use crate::moves::{self, Engine};
+use crate::position::{Color, Piece, Position, Square};
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TuningOptions {
+    /// how many self-play games to generate.
+    pub games: usize,
+    /// the search depth of the engine in the self-play games, at least 1.
+    pub depth: isize,
+    /// how many random moves open each game, so that the games differ.
+    pub random_plies: usize,
+    /// games still going after this many plies are scored as draws.
+    pub max_plies: usize,
+    /// how many rounds of local search to run at most.
+    pub iterations: usize,
+    pub seed: u64,
+}
+
+impl Default for TuningOptions {
+    fn default() -> Self {
+        TuningOptions{ games: 100, depth: 3, random_plies: 8, max_plies: 300, iterations: 20, seed: 0 }
+    }
+}
+
+/// A position, labelled with the result of the game it was played in:
+/// 1 if white won, 0 if black won, 0.5 for a draw.
+#[derive(Clone)]
+pub struct Sample {
+    pub position: Position,
+    pub result: f64,
+}
+
+/// A weight the tuning can change, with the path to it in the parameter file.
+struct Tunable {
+    name: String,
+    weight: Box<dyn Fn(&mut EvalParams) -> &mut isize>,
+}
+
+impl Tunable {
+    fn new(name: String, weight: impl Fn(&mut EvalParams) -> &mut isize + 'static) -> Self {
+        Tunable{ name, weight: Box::new(weight) }
+    }
+
+    /// Every weight except the material scale and the pawn value,
+    /// which set the unit the other weights are measured in.
+    fn all() -> Vec<Tunable> {
+        let pieces = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King];
+        let phases = [(false, "middlegame"), (true, "endgame")];
+        let piece_name = |piece: Piece| format!("{piece:?}").to_lowercase();
+
+        let mut result = vec![
+            Tunable::new("virtual_material_scale".to_string(), |params| &mut params.virtual_material_scale),
+            Tunable::new("positional_scale".to_string(), |params| &mut params.positional_scale),
+        ];
+
+        for (endgame, phase) in phases {
+            for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+                result.push(Tunable::new(format!("piece_values.{}.{phase}", piece_name(piece)),
+                    move |params| params.piece_values.get_mut(piece).get_mut(endgame)));
+            }
+            for piece in pieces {
+                result.push(Tunable::new(format!("king_mobility.{}.{phase}", piece_name(piece)),
+                    move |params| params.king_mobility.get_mut(piece).get_mut(endgame)));
+            }
+            result.push(Tunable::new(format!("lone_king_activity.{phase}"),
+                move |params| params.lone_king_activity.get_mut(endgame)));
+            for rank in 0..7 {
+                result.push(Tunable::new(format!("pawn_advancement.{phase}[{rank}]"),
+                    move |params| &mut params.pawn_advancement.get_mut(endgame)[rank]));
+            }
+            for piece in pieces {
+                for index in 0..64 {
+                    let square = Square::from_bit_position(index);
+                    result.push(Tunable::new(format!("piece_squares.{}.{phase}.{square}", piece_name(piece)),
+                        move |params| &mut params.piece_squares.get_mut(piece).get_mut(endgame).0[index as usize]));
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// Plays a game of the engine against itself from `start`, opening with `random_plies` random moves,
+/// and returns the result of the game, as in [`Sample`], with the positions played after the opening.
+pub fn play_game(start: &Position, params: &EvalParams, options: &TuningOptions, rng: &mut impl rand::Rng) -> (f64, Vec<Position>) {
+    let mut position = start.clone();
+    let mut repetitions = HashMap::from([(position.key(), 1)]);
+    let mut positions = vec![];
+    let mut engine = None;
+    let mut ply = 0;
+
+    let result = loop {
+        let legal_moves = moves::legal_moves(&position);
+        if legal_moves.is_empty() {
+            break match (moves::in_check(&position), position.turn) {
+                (false, _)           => 0.5,
+                (true, Color::White) => 0.0,
+                (true, Color::Black) => 1.0,
+            };
+        }
+        if repetitions[&position.key()] >= 3 || position.halfmove_clock >= 100 || ply >= options.max_plies {
+            break 0.5;
+        }
+
+        let mov = if ply < options.random_plies {
+            *legal_moves.choose(rng).expect("there are legal moves")
+        } else {
+            // checks are left out, since the evaluation does not see the threat.
+            if !moves::in_check(&position) {
+                positions.push(position.clone());
+            }
+
+            let engine = engine.get_or_insert_with(|| {
+                let mut engine = Engine::from_position(&position, options.depth);
+                engine.set_eval_params(params.clone());
+                engine
+            });
+            let (mov, _) = engine.suggest_move().expect("a search of depth 1 or more finds a move");
+            mov
+        };
+
+        if let Some(engine) = &mut engine {
+            engine.input_move(mov);
+        }
+        position.make_move(mov);
+        *repetitions.entry(position.key()).or_default() += 1;
+        ply += 1;
+    };
+
+    (result, positions)
+}
+
+/// Plays `options.games` self-play games from `start`.
+pub fn generate_samples(start: &Position, params: &EvalParams, options: &TuningOptions) -> Vec<Sample> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(options.seed);
+    let mut result = vec![];
+    for game in 0..options.games {
+        let (game_result, positions) = play_game(start, params, options, &mut rng);
+        eprintln!("game {}/{}: result {game_result}, {} positions", game + 1, options.games, positions.len());
+        result.extend(positions.into_iter().map(|position| Sample{ position, result: game_result }));
+    }
+    result
+}
+
+fn sigmoid(k: f64, score: isize) -> f64 {
+    1.0 / (1.0 + (-k * score as f64 / PAWN_SCORE as f64).exp())
+}
+
+/// The mean squared difference between the results and the predictions from the evaluation.
+pub fn error(samples: &[Sample], params: &EvalParams, k: f64) -> f64 {
+    let total = samples
+        .iter()
+        .map(|sample| {
+            let score = evaluation::trace(&sample.position, params).total().difference();
+            (sample.result - sigmoid(k, score)).powi(2)
+        })
+        .sum::<f64>();
+    total / samples.len().max(1) as f64
+}
+
+/// Finds the scaling of the sigmoid that best fits the samples with the given weights,
+/// so that the tuning changes the weights rather than just their scale.
+pub fn fit_scaling(samples: &[Sample], params: &EvalParams) -> f64 {
+    let (mut low, mut high) = (0.0, 10.0);
+    // the error is convex enough in k for a ternary search.
+    for _ in 0..50 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if error(samples, params, a) < error(samples, params, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Moves each weight up or down as long as that lowers the error, starting from `params`.
+pub fn tune(samples: &[Sample], params: &EvalParams, options: &TuningOptions) -> EvalParams {
+    let k = fit_scaling(samples, params);
+    let mut best = params.clone();
+    let mut best_error = error(samples, &best, k);
+    eprintln!("scaling {k:.4}, initial error {best_error:.6}");
+
+    for iteration in 0..options.iterations {
+        let mut improved = false;
+        for tunable in Tunable::all() {
+            let value = *(tunable.weight)(&mut best);
+            // larger weights move in larger steps, so that the scales do not take forever.
+            let step = (value.abs() / 16).max(1);
+            for candidate in [value + step, value - step] {
+                let mut params = best.clone();
+                *(tunable.weight)(&mut params) = candidate;
+                let candidate_error = error(samples, &params, k);
+                if candidate_error < best_error {
+                    eprintln!("{}: {value} -> {candidate}, error {candidate_error:.6}", tunable.name);
+                    best = params;
+                    best_error = candidate_error;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        eprintln!("iteration {}: error {best_error:.6}", iteration + 1);
+        if !improved { break }
+    }
+
+    best
+}
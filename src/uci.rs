@@ -0,0 +1,202 @@
+//! The Universal Chess Interface, through which GUIs and match tools drive the engine over stdin and stdout.
+//!
+//! Moves are written in coordinate notation, see [`crate::notation`].
+//! Positions given with `position fen` use the one-line notation of [`Position::parse`].
+
+use crate::evaluation::{EvalParams, PAWN_SCORE};
+use crate::moves::{Clock, Engine, SearchInfo, SearchLimits};
+use crate::notation::{coordinate, parse_coordinate};
+use crate::position::{Color, Position, Strictness};
+use crate::search_thread::SearchThread;
+use crate::transposition::TranspositionTable;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+const MAX_HASH_MB: usize = 4096;
+
+/// Formats the progress of a search as an `info` line.
+/// Scores are in centipawns, or in moves until mate, negative when the engine is getting mated.
+fn info_line(info: &SearchInfo) -> String {
+    let score = if Engine::is_mate_score(info.score) {
+        let moves = (Engine::MATE_SCORE - info.score.abs() + 1) / 2;
+        format!("mate {}", if info.score > 0 { moves } else { -moves })
+    } else {
+        format!("cp {}", info.score * 100 / PAWN_SCORE)
+    };
+    let pv = info.pv.iter().map(|&mov| coordinate(mov)).collect::<Vec<_>>().join(" ");
+    format!("info depth {} seldepth {} score {score} nodes {} nps {} time {} pv {pv}",
+        info.depth, info.seldepth, info.nodes, info.nps, info.elapsed.as_millis())
+}
+
+/// Parses the value following the keyword at `args[i]`.
+fn value_after<T: std::str::FromStr>(args: &[&str], i: usize) -> Result<T, String> {
+    args.get(i + 1)
+        .and_then(|value| value.parse().ok())
+        .ok_or(format!("{} needs a valid value", args[i]))
+}
+
+struct Uci {
+    search: SearchThread,
+    /// whether the last search only ends when it is stopped.
+    infinite: bool,
+}
+
+impl Uci {
+    fn new(params: EvalParams) -> Self {
+        let mut engine = Engine::from_position(&crate::start_position(), 1);
+        engine.set_eval_params(params);
+        engine.set_info_callback(|info| println!("{}", info_line(info)));
+        Uci{ search: SearchThread::new(engine), infinite: false }
+    }
+
+    /// The engine, after stopping the search if one is running.
+    fn engine(&mut self) -> &mut Engine {
+        self.search.engine()
+    }
+
+    fn identify(&self) {
+        println!("id name lh-chess");
+        println!("id author ap29600");
+        println!("option name Hash type spin default {} min 1 max {MAX_HASH_MB}", TranspositionTable::DEFAULT_SIZE_MB);
+        println!("option name Clear Hash type button");
+        println!("option name EvalFile type string default <empty>");
+        println!("option name DeltaPruning type check default true");
+        println!("option name UCI_Variant type combo default leftchess var leftchess");
+        println!("uciok");
+    }
+
+    /// `position startpos|fen <position> [moves <move>...]`
+    fn position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_at = args.iter().position(|&arg| arg == "moves").unwrap_or(args.len());
+        let pos = match &args[..moves_at] {
+            ["startpos"] => crate::start_position(),
+            ["fen", fen @ ..] => Position::parse(&fen.join(" "), Strictness::Strict)
+                .map_err(|err| format!("invalid position: {err}"))?,
+            _ => return Err("usage: position startpos|fen <position> [moves <move>...]".to_string()),
+        };
+
+        let engine = self.engine();
+        engine.set_position(&pos);
+        for &mov in args.iter().skip(moves_at + 1) {
+            let parsed = parse_coordinate(engine.current_position(), mov).map_err(|err| err.to_string())?;
+            engine.input_move(parsed);
+        }
+        Ok(())
+    }
+
+    /// `go [depth <plies>] [nodes <count>] [movetime <ms>] [wtime <ms>] [btime <ms>] [winc <ms>] [binc <ms>] [infinite]`
+    fn go(&mut self, args: &[&str]) -> Result<(), String> {
+        let engine = self.engine();
+        let turn = engine.current_position().turn;
+        let mut limits = SearchLimits::default();
+        let mut infinite = false;
+        let (mut remaining, mut increment) = (None, Duration::ZERO);
+
+        for (i, &arg) in args.iter().enumerate() {
+            let ms = |i| value_after(args, i).map(Duration::from_millis);
+            match (arg, turn) {
+                ("depth", _) => limits.max_depth = Some(value_after(args, i)?),
+                ("nodes", _) => limits.max_nodes = Some(value_after(args, i)?),
+                ("movetime", _) => limits.move_time = Some(ms(i)?),
+                ("wtime", Color::White) | ("btime", Color::Black) => remaining = Some(ms(i)?),
+                ("winc", Color::White) | ("binc", Color::Black) => increment = ms(i)?,
+                ("infinite", _) => infinite = true,
+                _ => (),
+            }
+        }
+        limits.clock = remaining.map(|remaining| Clock{ remaining, increment });
+        engine.set_limits(limits);
+
+        self.infinite = infinite;
+        self.search.start(move |engine, best| {
+            // in infinite mode the move is only sent once the GUI asks for it.
+            let stop = engine.stop_signal();
+            while infinite && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            match best {
+                Some(mov) => println!("bestmove {}", coordinate(mov)),
+                None => println!("bestmove 0000"),
+            }
+        });
+        Ok(())
+    }
+
+    /// `setoption name <name> [value <value>]`
+    fn set_option(&mut self, args: &[&str]) -> Result<(), String> {
+        let value_at = args.iter().position(|&arg| arg == "value").unwrap_or(args.len());
+        let ["name", name @ ..] = &args[..value_at] else {
+            return Err("usage: setoption name <name> [value <value>]".to_string());
+        };
+        let name = name.join(" ");
+        let value = args.get(value_at + 1..).unwrap_or_default().join(" ");
+
+        // option names are not case sensitive.
+        match name.to_lowercase().as_str() {
+            "hash" => {
+                let megabytes = value.parse::<usize>().map_err(|_| format!("invalid hash size: {value}"))?;
+                self.engine().set_hash_size(megabytes.clamp(1, MAX_HASH_MB));
+            },
+            "clear hash" => self.engine().clear_hash(),
+            "evalfile" => {
+                let params = if value.is_empty() || value == "<empty>" {
+                    EvalParams::default()
+                } else {
+                    let text = std::fs::read_to_string(&value).map_err(|err| format!("cannot read {value}: {err}"))?;
+                    EvalParams::from_toml(&text).map_err(|err| format!("invalid parameters in {value}: {err}"))?
+                };
+                self.engine().set_eval_params(params);
+            },
+            "deltapruning" => match value.as_str() {
+                "true" => self.engine().set_delta_pruning(true),
+                "false" => self.engine().set_delta_pruning(false),
+                _ => return Err(format!("invalid value for DeltaPruning: {value}")),
+            },
+            "uci_variant" if value == "leftchess" => (),
+            "uci_variant" => return Err(format!("unsupported variant: {value}")),
+            _ => return Err(format!("unknown option: {name}")),
+        }
+        Ok(())
+    }
+}
+
+/// Reads UCI commands from stdin until `quit` or the end of the input,
+/// answering `stop` and `isready` even while a search is running.
+pub fn run(params: EvalParams) {
+    let mut uci = Uci::new(params);
+    let mut quit = false;
+
+    for line in std::io::stdin().lines() {
+        let Ok(line) = line else { break };
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+
+        let result = match tokens.as_slice() {
+            [] => Ok(()),
+            ["uci"] => { uci.identify(); Ok(()) },
+            ["isready"] => { println!("readyok"); Ok(()) },
+            ["ucinewgame"] => {
+                let engine = uci.engine();
+                engine.clear_hash();
+                engine.set_position(&crate::start_position());
+                Ok(())
+            },
+            ["position", args @ ..] => uci.position(args),
+            ["go", args @ ..] => uci.go(args),
+            ["stop"] => { uci.engine(); Ok(()) },
+            ["setoption", args @ ..] => uci.set_option(args),
+            ["quit"] => { quit = true; break },
+            [command, ..] => Err(format!("unknown command: {command}")),
+        };
+
+        if let Err(err) = result {
+            println!("info string {err}");
+        }
+    }
+
+    // at the end of a script the last search still runs to its end, unless it would never end by itself.
+    if quit || uci.infinite {
+        uci.engine();
+    } else {
+        uci.search.wait();
+    }
+}
@@ -0,0 +1,250 @@
+//! The Chess Engine Communication Protocol (version 2), spoken by xboard and other variant-friendly GUIs.
+//!
+//! The engine advertises a single variant, `leftchess`. Moves are written in coordinate notation,
+//! see [`crate::notation`], and `setboard` takes the one-line notation of [`Position::parse`].
+
+use crate::evaluation::{EvalParams, PAWN_SCORE};
+use crate::moves::{self, Clock, Engine, SearchInfo, SearchLimits};
+use crate::position::{Color, Position, Strictness};
+use crate::notation::{coordinate, parse_coordinate};
+use crate::search_thread::SearchThread;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Formats the progress of a search as a line of thinking output: depth, score in centipawns,
+/// time in centiseconds, nodes and the principal variation.
+/// Mates are scored 100000 plus the number of moves to mate, negated when the engine is getting mated.
+fn thinking_line(info: &SearchInfo) -> String {
+    let score = if Engine::is_mate_score(info.score) {
+        let moves = (Engine::MATE_SCORE - info.score.abs() + 1) / 2;
+        (100000 + moves) * info.score.signum()
+    } else {
+        info.score * 100 / PAWN_SCORE
+    };
+    let pv = info.pv.iter().map(|&mov| coordinate(mov)).collect::<Vec<_>>().join(" ");
+    format!("{} {score} {} {} {pv}", info.depth, info.elapsed.as_millis() / 10, info.nodes)
+}
+
+/// The result command for a position in which the side to move has no legal moves.
+fn game_result(pos: &Position) -> Option<&'static str> {
+    if !moves::legal_moves(pos).is_empty() {
+        return None;
+    }
+    Some(match (moves::in_check(pos), pos.turn) {
+        (false, _)           => "1/2-1/2 {Stalemate}",
+        (true, Color::White) => "0-1 {Black mates}",
+        (true, Color::Black) => "1-0 {White mates}",
+    })
+}
+
+/// Parses the base time of the `level` command, given in minutes or as `minutes:seconds`.
+fn parse_base_time(s: &str) -> Option<Duration> {
+    let (minutes, seconds) = s.split_once(':').unwrap_or((s, "0"));
+    Some(Duration::from_secs(minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?))
+}
+
+struct Xboard {
+    search: SearchThread,
+    /// set when the search is abandoned, so that it does not play its move.
+    abort: Arc<AtomicBool>,
+    /// whether thinking output is printed.
+    post: Arc<AtomicBool>,
+    /// the side the engine plays, or `None` in force mode.
+    engine_color: Option<Color>,
+    max_depth: Option<isize>,
+    move_time: Option<Duration>,
+    /// the time left on the engine's clock, as last reported by `time`.
+    remaining: Option<Duration>,
+    increment: Duration,
+}
+
+impl Xboard {
+    fn new(params: EvalParams) -> Self {
+        let mut engine = Engine::from_position(&crate::start_position(), 1);
+        engine.set_eval_params(params);
+        let post = Arc::new(AtomicBool::new(false));
+        let thinking = post.clone();
+        engine.set_info_callback(move |info| {
+            if thinking.load(Ordering::Relaxed) {
+                println!("{}", thinking_line(info));
+            }
+        });
+
+        Xboard{
+            search: SearchThread::new(engine),
+            abort: Arc::new(AtomicBool::new(false)),
+            post,
+            engine_color: Some(Color::Black),
+            max_depth: None,
+            move_time: None,
+            remaining: None,
+            increment: Duration::ZERO,
+        }
+    }
+
+    /// The engine, after abandoning the search if one is running.
+    fn engine(&mut self) -> &mut Engine {
+        self.abort.store(true, Ordering::Relaxed);
+        self.search.engine()
+    }
+
+    /// Starts searching for a move if it is the engine's turn.
+    fn think(&mut self) {
+        let limits = SearchLimits{
+            max_depth: self.max_depth,
+            max_nodes: None,
+            move_time: self.move_time,
+            clock: self.remaining.map(|remaining| Clock{ remaining, increment: self.increment }),
+        };
+        let engine_color = self.engine_color;
+
+        let engine = self.engine();
+        if engine_color != Some(engine.current_position().turn) {
+            return;
+        }
+        if let Some(result) = game_result(engine.current_position()) {
+            println!("{result}");
+            return;
+        }
+        engine.set_limits(limits);
+
+        self.abort.store(false, Ordering::Relaxed);
+        let abort = self.abort.clone();
+        self.search.start(move |engine, best| {
+            if let (Some(mov), false) = (best, abort.load(Ordering::Relaxed)) {
+                engine.input_move(mov);
+                println!("move {}", coordinate(mov));
+                if let Some(result) = game_result(engine.current_position()) {
+                    println!("{result}");
+                }
+            }
+        });
+    }
+
+    fn user_move(&mut self, mov: &str) -> Result<(), String> {
+        let engine = self.engine();
+        let parsed = parse_coordinate(engine.current_position(), mov)
+            .map_err(|err| format!("Illegal move ({err}): {mov}"))?;
+        engine.input_move(parsed);
+        if let Some(result) = game_result(engine.current_position()) {
+            println!("{result}");
+            return Ok(());
+        }
+        self.think();
+        Ok(())
+    }
+
+    fn set_board(&mut self, fen: &str) -> Result<(), String> {
+        let pos = Position::parse(fen, Strictness::Strict)
+            .map_err(|err| format!("tellusererror Illegal position: {err}"))?;
+        self.engine().set_position(&pos);
+        Ok(())
+    }
+
+    /// `level <moves per session> <base time> <increment>`; the moves per session are ignored,
+    /// and the base time stands for the clock until `time` reports the time left.
+    fn level(&mut self, args: &[&str]) -> Result<(), String> {
+        let [_, base, increment] = args else {
+            return Err("Error (usage: level <moves> <base> <increment>): level".to_string());
+        };
+        let base = parse_base_time(base);
+        let increment = increment.parse::<f64>().ok().filter(|increment| *increment >= 0.0);
+        let (Some(base), Some(increment)) = (base, increment) else {
+            return Err(format!("Error (invalid time control): level {}", args.join(" ")));
+        };
+        self.remaining = Some(base);
+        self.increment = Duration::from_secs_f64(increment);
+        self.move_time = None;
+        Ok(())
+    }
+}
+
+/// Reads CECP commands from stdin until `quit` or the end of the input;
+/// `?` makes a running search move at once.
+pub fn run(params: EvalParams) {
+    let mut xboard = Xboard::new(params);
+
+    for line in std::io::stdin().lines() {
+        let Ok(line) = line else { break };
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+
+        let result = match tokens.as_slice() {
+            [] | ["xboard"] | ["accepted", ..] | ["rejected", ..] | ["random"] | ["hard"] | ["easy"]
+            | ["computer"] | ["name", ..] | ["rating", ..] | ["otim", _] | ["result", ..] => Ok(()),
+            ["protover", _] => {
+                println!("feature myname=\"lh-chess\" variants=\"leftchess\" usermove=1 setboard=1 ping=1 \
+                    colors=0 analyze=0 sigint=0 sigterm=0 done=1");
+                Ok(())
+            },
+            ["new"] => {
+                let engine = xboard.engine();
+                engine.clear_hash();
+                engine.set_position(&crate::start_position());
+                xboard.engine_color = Some(Color::Black);
+                xboard.max_depth = None;
+                xboard.move_time = None;
+                Ok(())
+            },
+            ["variant", "leftchess"] => Ok(()),
+            ["variant", variant] => Err(format!("Error (unsupported variant): {variant}")),
+            ["force"] => {
+                xboard.engine();
+                xboard.engine_color = None;
+                Ok(())
+            },
+            ["go"] => {
+                xboard.engine_color = Some(xboard.engine().current_position().turn);
+                xboard.think();
+                Ok(())
+            },
+            ["usermove", mov] => xboard.user_move(mov),
+            ["?"] => {
+                xboard.search.stop();
+                Ok(())
+            },
+            ["undo"] => {
+                xboard.engine().roll_back();
+                Ok(())
+            },
+            ["remove"] => {
+                let engine = xboard.engine();
+                engine.roll_back();
+                engine.roll_back();
+                Ok(())
+            },
+            ["setboard", fen @ ..] => xboard.set_board(&fen.join(" ")),
+            ["post"] => {
+                xboard.post.store(true, Ordering::Relaxed);
+                Ok(())
+            },
+            ["nopost"] => {
+                xboard.post.store(false, Ordering::Relaxed);
+                Ok(())
+            },
+            ["sd", depth] => depth.parse()
+                .map(|depth| xboard.max_depth = Some(depth))
+                .map_err(|_| format!("Error (invalid depth): {depth}")),
+            ["st", seconds] => seconds.parse()
+                .map(|seconds| xboard.move_time = Some(Duration::from_secs(seconds)))
+                .map_err(|_| format!("Error (invalid time): {seconds}")),
+            ["level", args @ ..] => xboard.level(args),
+            // in centiseconds.
+            ["time", time] => time.parse::<u64>()
+                .map(|time| xboard.remaining = Some(Duration::from_millis(time * 10)))
+                .map_err(|_| format!("Error (invalid time): {time}")),
+            ["ping", n] => {
+                println!("pong {n}");
+                Ok(())
+            },
+            ["quit"] => break,
+            [command, ..] => Err(format!("Error (unknown command): {command}")),
+        };
+
+        if let Err(err) = result {
+            println!("{err}");
+        }
+    }
+
+    xboard.engine();
+}
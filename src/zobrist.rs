@@ -0,0 +1,67 @@
+use crate::position::{Color, Piece, Position, Square};
+
+// splitmix64, so that the keys can be generated at compile time.
+const fn split_mix(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (state, z ^ (z >> 31))
+}
+
+const PIECE_KEYS: [[u64; 64]; 12] = {
+    let mut keys = [[0; 64]; 12];
+    let mut state = 0x6c68_2d63_6865_7373; // "lh-chess"
+    let mut kind = 0;
+    while kind < 12 {
+        let mut square = 0;
+        while square < 64 {
+            let (next_state, key) = split_mix(state);
+            state = next_state;
+            keys[kind][square] = key;
+            square += 1;
+        }
+        kind += 1;
+    }
+    keys
+};
+
+const BLACK_TO_MOVE_KEY: u64 = split_mix(0x7475_726e).1;
+
+pub fn piece_key(color: Color, piece: Piece, square: Square) -> u64 {
+    let color_index = match color {
+        Color::White => 0,
+        Color::Black => 6,
+    };
+    let piece_index = match piece {
+        Piece::Pawn   => 0,
+        Piece::Knight => 1,
+        Piece::Bishop => 2,
+        Piece::Rook   => 3,
+        Piece::Queen  => 4,
+        Piece::King   => 5,
+    };
+    PIECE_KEYS[color_index + piece_index][square.to_bit_position() as usize]
+}
+
+/// The key that is xor-ed in whenever the side to move changes.
+pub fn turn_key() -> u64 {
+    BLACK_TO_MOVE_KEY
+}
+
+/// Computes the key of a position from scratch.
+/// [`Position::make_move`] and [`Position::unmake_move`] keep it up to date incrementally instead.
+pub fn hash(pos: &Position) -> u64 {
+    let mut result = 0;
+    for color in [Color::White, Color::Black] {
+        for piece in [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King] {
+            for square in *pos.player(color).pieces(piece) {
+                result ^= piece_key(color, piece, square);
+            }
+        }
+    }
+    if pos.turn == Color::Black {
+        result ^= BLACK_TO_MOVE_KEY;
+    }
+    result
+}
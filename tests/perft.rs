@@ -0,0 +1,111 @@
+//! Regression tests for the move generator, run against the `perft` and `legal-moves` subcommands.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w 0 1";
+
+/// The example position from the README.
+const README_BLACK: &str = "rnbq1k1r/pp1bpPnp/3P4/p5pQ/8/5P2/1PPPPRB1/RNB1K1N1 b 0 1";
+const README_WHITE: &str = "rnbq1k1r/pp1bpPnp/3P4/p5pQ/8/5P2/1PPPPRB1/RNB1K1N1 w 0 1";
+
+fn run(args: &[&str], position: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lh-chess"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("can start lh-chess");
+
+    child.stdin.take().expect("stdin is piped").write_all(position.as_bytes()).expect("can write position");
+    let output = child.wait_with_output().expect("lh-chess terminates");
+    assert!(output.status.success(), "lh-chess {args:?} failed on {position}");
+    String::from_utf8(output.stdout).expect("output is utf-8")
+}
+
+fn perft(position: &str, depth: usize) -> u64 {
+    let output = run(&["perft", &depth.to_string()], position);
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("total: "))
+        .expect("perft prints a total")
+        .parse()
+        .expect("total is a number")
+}
+
+fn legal_moves(position: &str) -> Vec<String> {
+    run(&["legal-moves"], position).lines().map(str::to_string).collect()
+}
+
+#[test]
+fn perft_start_position() {
+    for (depth, expected) in [(0, 1), (1, 11), (2, 121), (3, 1860), (4, 28402)] {
+        assert_eq!(perft(START, depth), expected, "depth {depth}");
+    }
+}
+
+#[test]
+fn perft_readme_position() {
+    for (depth, expected) in [(1, 41), (2, 995), (3, 35161)] {
+        assert_eq!(perft(README_BLACK, depth), expected, "black to move, depth {depth}");
+    }
+    for (depth, expected) in [(1, 23), (2, 833), (3, 22874)] {
+        assert_eq!(perft(README_WHITE, depth), expected, "white to move, depth {depth}");
+    }
+}
+
+#[test]
+fn grid_and_one_line_notation_agree() {
+    let grid = "rnbqkbnr\npppppppp\n........\n........\n........\n........\nPPPPPPPP\nRNBQKBNR\n";
+    assert_eq!(perft(grid, 3), perft(START, 3));
+}
+
+#[test]
+fn readme_rook_moves_like_a_knight() {
+    let mut rook_moves = legal_moves(README_BLACK)
+        .into_iter()
+        .filter(|mov| mov.starts_with("rA8"))
+        .collect::<Vec<_>>();
+    rook_moves.sort();
+    assert_eq!(rook_moves, ["rA8B6", "rA8C7"]);
+}
+
+#[test]
+fn readme_queen_moves_like_a_pawn() {
+    let queen_moves = legal_moves(README_WHITE)
+        .into_iter()
+        .filter(|mov| mov.starts_with("QH5"))
+        .collect::<Vec<_>>();
+    assert_eq!(queen_moves, ["QH5H6"]);
+}
+
+#[test]
+fn readme_pawn_moves_like_a_queen() {
+    let mut pawn_moves = legal_moves(README_BLACK)
+        .into_iter()
+        .filter(|mov| mov.starts_with("pG5"))
+        .collect::<Vec<_>>();
+    pawn_moves.sort();
+
+    let mut expected = [
+        "pG5F6", "pG5G6", "pG5H6",
+        "pG5B5", "pG5C5", "pG5D5", "pG5E5", "pG5F5", "pG5xQH5",
+        "pG5F4", "pG5G4", "pG5H4",
+        "pG5E3", "pG5G3",
+        "pG5xPD2", "pG5xBG2",
+    ];
+    expected.sort();
+
+    assert_eq!(pawn_moves, expected);
+}
+
+#[test]
+fn lone_rook_slides_across_the_board() {
+    let rook_moves = legal_moves("8/8/7k/8/8/6K1/8/R7 w 0 1")
+        .into_iter()
+        .filter(|mov| mov.starts_with("RA1"))
+        .collect::<Vec<_>>();
+    assert_eq!(rook_moves.len(), 14);
+    assert!(rook_moves.iter().any(|mov| mov == "RA1A8"));
+    assert!(rook_moves.iter().any(|mov| mov == "RA1H1"));
+}